@@ -0,0 +1,134 @@
+use crate::config::RendererConfig;
+use crate::error::RendererError;
+
+// -----------------------------------------------------------------------------
+//     - Adapters -
+// -----------------------------------------------------------------------------
+
+/// Every adapter on the given backends, so users can tell us what they ran
+/// on.
+pub fn list_adapters(backends: wgpu::BackendBit) -> Vec<wgpu::AdapterInfo> {
+    wgpu::Instance::new(backends)
+        .enumerate_adapters(backends)
+        .map(|adapter| adapter.get_info())
+        .collect()
+}
+
+/// Pick the adapter by name if the config asks for one, otherwise let wgpu
+/// pick one with the preferred power usage. An adapter picked by name is
+/// not checked against the surface.
+pub async fn select_adapter(
+    instance: &wgpu::Instance,
+    config: &RendererConfig,
+    surface: Option<&wgpu::Surface>,
+) -> Result<wgpu::Adapter, RendererError> {
+    match &config.adapter_name {
+        Some(name) => {
+            let needle = name.to_lowercase();
+            instance
+                .enumerate_adapters(config.backends)
+                .find(|adapter| adapter.get_info().name.to_lowercase().contains(&needle))
+                .ok_or_else(|| RendererError::AdapterNotFound(name.clone()))
+        }
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: config.power_preference,
+                compatible_surface: surface,
+            })
+            .await
+            .ok_or(RendererError::NoAdapter),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Parsing -
+//     For environment variables and command line arguments
+// -----------------------------------------------------------------------------
+
+/// A comma separated list, e.g. "vulkan,metal". Case doesn't matter.
+pub fn parse_backends(s: &str) -> Option<wgpu::BackendBit> {
+    let mut backends = wgpu::BackendBit::empty();
+
+    for name in s.split(',').map(|name| name.trim().to_lowercase()) {
+        backends |= match name.as_str() {
+            "vulkan" | "vk" => wgpu::BackendBit::VULKAN,
+            "gl" | "opengl" => wgpu::BackendBit::GL,
+            "metal" | "mtl" => wgpu::BackendBit::METAL,
+            "dx12" | "d3d12" => wgpu::BackendBit::DX12,
+            "dx11" | "d3d11" => wgpu::BackendBit::DX11,
+            "primary" => wgpu::BackendBit::PRIMARY,
+            "secondary" => wgpu::BackendBit::SECONDARY,
+            "all" => wgpu::BackendBit::all(),
+            _ => return None,
+        };
+    }
+
+    Some(backends)
+}
+
+pub fn parse_power_preference(s: &str) -> Option<wgpu::PowerPreference> {
+    match s.trim().to_lowercase().as_str() {
+        "default" => Some(wgpu::PowerPreference::Default),
+        "low" | "low-power" | "lowpower" => Some(wgpu::PowerPreference::LowPower),
+        "high" | "high-performance" | "highperformance" => {
+            Some(wgpu::PowerPreference::HighPerformance)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_are_parsed() {
+        assert_eq!(parse_backends("vulkan"), Some(wgpu::BackendBit::VULKAN));
+        assert_eq!(parse_backends(" VK "), Some(wgpu::BackendBit::VULKAN));
+        assert_eq!(parse_backends("Metal"), Some(wgpu::BackendBit::METAL));
+        assert_eq!(parse_backends("all"), Some(wgpu::BackendBit::all()));
+        assert_eq!(
+            parse_backends("dx12, D3D11"),
+            Some(wgpu::BackendBit::DX12 | wgpu::BackendBit::DX11)
+        );
+        assert_eq!(
+            parse_backends("gl,primary"),
+            Some(wgpu::BackendBit::GL | wgpu::BackendBit::PRIMARY)
+        );
+        // Repeats are harmless
+        assert_eq!(parse_backends("gl,opengl"), Some(wgpu::BackendBit::GL));
+    }
+
+    #[test]
+    fn invalid_backends_are_rejected() {
+        for s in &[
+            "",
+            " ",
+            "vulkan,",
+            "vulkan,,gl",
+            "directx",
+            "vulkan;gl",
+            "vulkan gl",
+        ] {
+            assert_eq!(parse_backends(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn power_preferences_are_parsed() {
+        use wgpu::PowerPreference::*;
+
+        assert_eq!(parse_power_preference("default"), Some(Default));
+        assert_eq!(parse_power_preference(" Low "), Some(LowPower));
+        assert_eq!(parse_power_preference("low-power"), Some(LowPower));
+        assert_eq!(parse_power_preference("HIGH"), Some(HighPerformance));
+        assert_eq!(
+            parse_power_preference("highperformance"),
+            Some(HighPerformance)
+        );
+
+        for s in &["", "medium", "low,high", "low power"] {
+            assert_eq!(parse_power_preference(s), None, "{:?}", s);
+        }
+    }
+}
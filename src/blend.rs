@@ -0,0 +1,323 @@
+// Part of the drawing API, the demo doesn't convert colours itself
+#![allow(dead_code)]
+
+use crate::render::Pixel;
+
+// -----------------------------------------------------------------------------
+//     - Blend mode -
+//     How a source pixel is combined with the pixel already in the buffer.
+//     The separable modes follow the W3C compositing spec, so the source is
+//     composited "over" the destination with the blend function applied
+//     where the two overlap.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Overwrite, alpha and all
+    #[default]
+    Replace,
+    SourceOver,
+    Additive,
+    Multiply,
+    Screen,
+    /// Destination minus source, the destination alpha is left as is
+    Subtract,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 8] = [
+        BlendMode::Replace,
+        BlendMode::SourceOver,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Subtract,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+    ];
+}
+
+// -----------------------------------------------------------------------------
+//     - Alpha mode -
+//     How the colour channels of a buffer relate to its alpha channel
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AlphaMode {
+    /// Colour is stored as is, independent of alpha
+    #[default]
+    Straight,
+    /// Colour is already multiplied by alpha
+    Premultiplied,
+}
+
+// -----------------------------------------------------------------------------
+//     - Colour space -
+//     Pixels are sRGB encoded, the gpu blends in linear space.
+// -----------------------------------------------------------------------------
+pub fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f64) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+// -----------------------------------------------------------------------------
+//     - Blending -
+//     Integer only, every division rounds to nearest, so the same input
+//     always gives the same output on every platform.
+// -----------------------------------------------------------------------------
+
+/// `x / 255` rounded to nearest. 255 is odd so there are no ties.
+fn div255(x: u32) -> u32 {
+    (x + 127) / 255
+}
+
+pub fn premultiply(p: Pixel) -> Pixel {
+    let a = p.a as u32;
+    Pixel {
+        r: div255(p.r as u32 * a) as u8,
+        g: div255(p.g as u32 * a) as u8,
+        b: div255(p.b as u32 * a) as u8,
+        a: p.a,
+    }
+}
+
+pub fn unpremultiply(p: Pixel) -> Pixel {
+    let a = p.a as u32;
+    // Fully transparent pixels have no colour left to recover
+    let channel = |c: u8| {
+        (c as u32 * 255 + a / 2)
+            .checked_div(a)
+            .unwrap_or(0)
+            .min(255) as u8
+    };
+
+    Pixel {
+        r: channel(p.r),
+        g: channel(p.g),
+        b: channel(p.b),
+        a: p.a,
+    }
+}
+
+/// Blend `src` onto `dst`. Both pixels, and the result, are in the given
+/// alpha mode.
+pub fn blend(src: Pixel, dst: Pixel, mode: BlendMode, alpha: AlphaMode) -> Pixel {
+    match (mode, alpha) {
+        (BlendMode::Replace, _) => src,
+        (_, AlphaMode::Premultiplied) => blend_premultiplied(src, dst, mode),
+        (_, AlphaMode::Straight) => blend_straight(src, dst, mode),
+    }
+}
+
+/// The premultiplied formulas on straight pixels, with the result divided
+/// by its alpha. Worked out over a common denominator and rounded once,
+/// going through premultiplied pixels would round three times.
+fn blend_straight(src: Pixel, dst: Pixel, mode: BlendMode) -> Pixel {
+    let (sa, da) = (src.a as u64, dst.a as u64);
+
+    // Alpha is over 255^2, premultiplied colour over 255^4
+    let (a, channel): (u64, &dyn Fn(u64, u64) -> u64) = match mode {
+        BlendMode::Replace => return src,
+        BlendMode::Additive => ((sa + da).min(255) * 255, &|s, d| {
+            (sa * s + da * d).min(255 * 255) * 255 * 255
+        }),
+        BlendMode::Subtract => (da * 255, &|s, d| {
+            (da * d).saturating_sub(sa * s) * 255 * 255
+        }),
+        mode => {
+            // Cs * (1 - ab) + B(Cb, Cs) * ab, composited over, with B over 255^2
+            let mix: &dyn Fn(u64, u64) -> u64 = match mode {
+                BlendMode::Multiply => &|s, d| s * d,
+                BlendMode::Screen => &|s, d| (s + d) * 255 - s * d,
+                BlendMode::Darken => &|s, d| s.min(d) * 255,
+                BlendMode::Lighten => &|s, d| s.max(d) * 255,
+                _ => &|s, _| s * 255,
+            };
+            (sa * 255 + da * (255 - sa), &move |s, d| {
+                sa * (255 - da) * s * 255 + sa * da * mix(s, d) + (255 - sa) * da * d * 255
+            })
+        }
+    };
+
+    // Colour over alpha, times 255 and rounded half up
+    let channel = |s: u8, d: u8| {
+        let (num, den) = (2 * channel(s as u64, d as u64), 2 * 255 * a);
+        (num + den / 2).checked_div(den).unwrap_or(0).min(255) as u8
+    };
+
+    Pixel {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: div255(a as u32) as u8,
+    }
+}
+
+fn blend_premultiplied(src: Pixel, dst: Pixel, mode: BlendMode) -> Pixel {
+    let (sa, da) = (src.a as u32, dst.a as u32);
+
+    // Alpha of "source over destination", shared by most modes
+    let over_alpha = div255(sa * 255 + da * (255 - sa));
+
+    let (a, channel): (u32, &dyn Fn(u32, u32) -> u32) = match mode {
+        BlendMode::Replace => return src,
+        BlendMode::SourceOver => (over_alpha, &|s, d| div255(s * 255 + d * (255 - sa))),
+        BlendMode::Additive => ((sa + da).min(255), &|s, d| (s + d).min(255)),
+        BlendMode::Multiply => (over_alpha, &|s, d| {
+            div255(s * (255 - da) + d * (255 - sa) + s * d)
+        }),
+        BlendMode::Screen => (over_alpha, &|s, d| div255((s + d) * 255 - s * d)),
+        BlendMode::Subtract => (da, &|s, d| d.saturating_sub(s)),
+        BlendMode::Darken => (over_alpha, &|s, d| {
+            div255((s + d) * 255 - (s * da).max(d * sa))
+        }),
+        BlendMode::Lighten => (over_alpha, &|s, d| {
+            div255((s + d) * 255 - (s * da).min(d * sa))
+        }),
+    };
+
+    // Keep the result a valid premultiplied colour
+    let channel = |s: u8, d: u8| channel(s as u32, d as u32).min(a) as u8;
+
+    Pixel {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: a as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::test_util::{pixel, rgba, Rgba};
+
+    // Straight alpha (src, dst, result), worked out with exact fractions
+    // from the W3C compositing spec: Cs' = (1 - ab) * Cs + ab * B(Cb, Cs),
+    // composited source over and rounded half up. Additive and Subtract
+    // aren't in the spec and follow the definitions above instead.
+    const OPAQUE: (Rgba, Rgba) = ((204, 102, 51, 255), (51, 153, 255, 255));
+    const HALF_OVER_OPAQUE: (Rgba, Rgba) = ((255, 0, 0, 128), (0, 0, 255, 255));
+    const HALF_OVER_HALF: (Rgba, Rgba) = ((255, 0, 0, 128), (0, 0, 255, 128));
+    const OVER_CLEAR: (Rgba, Rgba) = ((0, 255, 0, 64), (255, 255, 255, 0));
+
+    const GOLDEN: [(BlendMode, (Rgba, Rgba), Rgba); 32] = [
+        (BlendMode::Replace, OPAQUE, (204, 102, 51, 255)),
+        (BlendMode::Replace, HALF_OVER_OPAQUE, (255, 0, 0, 128)),
+        (BlendMode::Replace, HALF_OVER_HALF, (255, 0, 0, 128)),
+        (BlendMode::Replace, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::SourceOver, OPAQUE, (204, 102, 51, 255)),
+        (BlendMode::SourceOver, HALF_OVER_OPAQUE, (128, 0, 127, 255)),
+        (BlendMode::SourceOver, HALF_OVER_HALF, (170, 0, 85, 192)),
+        (BlendMode::SourceOver, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::Additive, OPAQUE, (255, 255, 255, 255)),
+        (BlendMode::Additive, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Additive, HALF_OVER_HALF, (128, 0, 128, 255)),
+        (BlendMode::Additive, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::Multiply, OPAQUE, (41, 61, 51, 255)),
+        (BlendMode::Multiply, HALF_OVER_OPAQUE, (0, 0, 127, 255)),
+        (BlendMode::Multiply, HALF_OVER_HALF, (85, 0, 85, 192)),
+        (BlendMode::Multiply, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::Screen, OPAQUE, (214, 194, 255, 255)),
+        (BlendMode::Screen, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Screen, HALF_OVER_HALF, (170, 0, 170, 192)),
+        (BlendMode::Screen, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::Subtract, OPAQUE, (0, 51, 204, 255)),
+        (BlendMode::Subtract, HALF_OVER_OPAQUE, (0, 0, 255, 255)),
+        (BlendMode::Subtract, HALF_OVER_HALF, (0, 0, 255, 128)),
+        (BlendMode::Subtract, OVER_CLEAR, (0, 0, 0, 0)),
+        (BlendMode::Darken, OPAQUE, (51, 102, 51, 255)),
+        (BlendMode::Darken, HALF_OVER_OPAQUE, (0, 0, 127, 255)),
+        (BlendMode::Darken, HALF_OVER_HALF, (85, 0, 85, 192)),
+        (BlendMode::Darken, OVER_CLEAR, (0, 255, 0, 64)),
+        (BlendMode::Lighten, OPAQUE, (204, 153, 255, 255)),
+        (BlendMode::Lighten, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Lighten, HALF_OVER_HALF, (170, 0, 170, 192)),
+        (BlendMode::Lighten, OVER_CLEAR, (0, 255, 0, 64)),
+    ];
+
+    // The same, with src and dst premultiplied first
+    const PREMULTIPLIED_GOLDEN: [(BlendMode, (Rgba, Rgba), Rgba); 32] = [
+        (BlendMode::Replace, OPAQUE, (204, 102, 51, 255)),
+        (BlendMode::Replace, HALF_OVER_OPAQUE, (128, 0, 0, 128)),
+        (BlendMode::Replace, HALF_OVER_HALF, (128, 0, 0, 128)),
+        (BlendMode::Replace, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::SourceOver, OPAQUE, (204, 102, 51, 255)),
+        (BlendMode::SourceOver, HALF_OVER_OPAQUE, (128, 0, 127, 255)),
+        (BlendMode::SourceOver, HALF_OVER_HALF, (128, 0, 64, 192)),
+        (BlendMode::SourceOver, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::Additive, OPAQUE, (255, 255, 255, 255)),
+        (BlendMode::Additive, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Additive, HALF_OVER_HALF, (128, 0, 128, 255)),
+        (BlendMode::Additive, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::Multiply, OPAQUE, (41, 61, 51, 255)),
+        (BlendMode::Multiply, HALF_OVER_OPAQUE, (0, 0, 127, 255)),
+        (BlendMode::Multiply, HALF_OVER_HALF, (64, 0, 64, 192)),
+        (BlendMode::Multiply, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::Screen, OPAQUE, (214, 194, 255, 255)),
+        (BlendMode::Screen, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Screen, HALF_OVER_HALF, (128, 0, 128, 192)),
+        (BlendMode::Screen, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::Subtract, OPAQUE, (0, 51, 204, 255)),
+        (BlendMode::Subtract, HALF_OVER_OPAQUE, (0, 0, 255, 255)),
+        (BlendMode::Subtract, HALF_OVER_HALF, (0, 0, 128, 128)),
+        (BlendMode::Subtract, OVER_CLEAR, (0, 0, 0, 0)),
+        (BlendMode::Darken, OPAQUE, (51, 102, 51, 255)),
+        (BlendMode::Darken, HALF_OVER_OPAQUE, (0, 0, 127, 255)),
+        (BlendMode::Darken, HALF_OVER_HALF, (64, 0, 64, 192)),
+        (BlendMode::Darken, OVER_CLEAR, (0, 64, 0, 64)),
+        (BlendMode::Lighten, OPAQUE, (204, 153, 255, 255)),
+        (BlendMode::Lighten, HALF_OVER_OPAQUE, (128, 0, 255, 255)),
+        (BlendMode::Lighten, HALF_OVER_HALF, (128, 0, 128, 192)),
+        (BlendMode::Lighten, OVER_CLEAR, (0, 64, 0, 64)),
+    ];
+
+    #[test]
+    fn straight_matches_golden() {
+        for &(mode, (src, dst), expected) in &GOLDEN {
+            let actual = blend(pixel(src), pixel(dst), mode, AlphaMode::Straight);
+            let what = format!("{:?} {:?} on {:?}", mode, src, dst);
+            assert_eq!(rgba(&actual), expected, "{}", what);
+        }
+    }
+
+    #[test]
+    fn premultiplied_matches_golden() {
+        for &(mode, (src, dst), expected) in &PREMULTIPLIED_GOLDEN {
+            let (src, dst) = (premultiply(pixel(src)), premultiply(pixel(dst)));
+            let actual = blend(src, dst, mode, AlphaMode::Premultiplied);
+            let what = format!("{:?} {:?} on {:?} premultiplied", mode, src, dst);
+            assert_eq!(rgba(&actual), expected, "{}", what);
+        }
+    }
+
+    #[test]
+    fn every_mode_is_covered() {
+        for mode in &BlendMode::ALL {
+            assert!(GOLDEN.iter().any(|(m, _, _)| m == mode), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for c in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(c)), c);
+        }
+        assert_eq!(linear_to_srgb(-1.0), 0);
+        assert_eq!(linear_to_srgb(2.0), 255);
+    }
+}
@@ -0,0 +1,297 @@
+// Sprite API, the demo doesn't load or blit any images
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use crate::blend::{premultiply, unpremultiply, AlphaMode, BlendMode};
+use crate::draw::Rect;
+use crate::render::{Pixel, PixelBuffer};
+
+// -----------------------------------------------------------------------------
+//     - Blit options -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BlitOptions {
+    pub blend_mode: BlendMode,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Source pixels with this colour are skipped. Only rgb is compared,
+    /// alpha is ignored.
+    pub color_key: Option<Pixel>,
+    /// Source pixels with an alpha below this are skipped, the rest are
+    /// drawn fully opaque.
+    pub alpha_threshold: Option<u8>,
+}
+
+// -----------------------------------------------------------------------------
+//     - Blitting -
+// -----------------------------------------------------------------------------
+impl PixelBuffer {
+    /// Load an image, anything the `image` crate can read.
+    pub fn open(path: impl AsRef<Path>) -> image::ImageResult<Self> {
+        Ok(Self::from_image(&image::open(path)?))
+    }
+
+    /// Save as an image, the format comes from the extension of `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> image::ImageResult<()> {
+        image::save_buffer(
+            path,
+            self,
+            self.width(),
+            self.height(),
+            image::ColorType::Rgba8,
+        )
+    }
+
+    pub fn from_image(image: &image::DynamicImage) -> Self {
+        let rgba = image.to_rgba8();
+        let mut buffer = Self::new(rgba.width(), rgba.height());
+
+        for (dst, src) in buffer.enumerate_mut().map(|(_, _, p)| p).zip(rgba.pixels()) {
+            let [r, g, b, a] = src.0;
+            *dst = Pixel { r, g, b, a };
+        }
+
+        buffer
+    }
+
+    /// Copy `src_rect` of `src` to (`dst_x`, `dst_y`), blended with
+    /// `options.blend_mode`. Clipped against both buffers.
+    pub fn blit(
+        &mut self,
+        src: &PixelBuffer,
+        src_rect: Rect,
+        dst_x: i32,
+        dst_y: i32,
+        options: BlitOptions,
+    ) {
+        // Only walk the part of the rect that is inside both buffers
+        let cols = clip_axis(
+            (dst_x, self.width()),
+            (src_rect.x, src_rect.width),
+            src.width(),
+            options.flip_x,
+        );
+        let rows = clip_axis(
+            (dst_y, self.height()),
+            (src_rect.y, src_rect.height),
+            src.height(),
+            options.flip_y,
+        );
+
+        for (y, sy) in rows {
+            for (x, sx) in cols.clone() {
+                let mut pixel = match src.get(sx, sy) {
+                    Some(p) => *p,
+                    None => continue,
+                };
+
+                if let Some(key) = options.color_key {
+                    if (pixel.r, pixel.g, pixel.b) == (key.r, key.g, key.b) {
+                        continue;
+                    }
+                }
+
+                match (src.alpha_mode(), self.alpha_mode()) {
+                    (AlphaMode::Straight, AlphaMode::Premultiplied) => pixel = premultiply(pixel),
+                    (AlphaMode::Premultiplied, AlphaMode::Straight) => pixel = unpremultiply(pixel),
+                    _ => {}
+                }
+
+                if let Some(threshold) = options.alpha_threshold {
+                    if pixel.a < threshold {
+                        continue;
+                    }
+                    if self.alpha_mode() == AlphaMode::Premultiplied {
+                        pixel = unpremultiply(pixel);
+                    }
+                    pixel.a = 255;
+                }
+
+                self.plot(x, y, pixel, options.blend_mode);
+            }
+        }
+    }
+}
+
+/// One axis of a blit: every destination coordinate inside the
+/// destination (`dst`, of length `dst_len`) that reads from inside the
+/// source, paired with the source coordinate it reads. The run starts at
+/// `src` in the source and is `len` long, backwards when flipped. In i64,
+/// so rects near the i32 limits don't overflow.
+fn clip_axis(
+    (dst, dst_len): (i32, u32),
+    (src, len): (i32, u32),
+    src_len: u32,
+    flip: bool,
+) -> impl Iterator<Item = (i32, u32)> + Clone {
+    let (dst, src, len) = (dst as i64, src as i64, len as i64);
+    let (dst_len, src_len) = (dst_len as i64, src_len as i64);
+
+    // Offsets into the run that land in the destination, and that read
+    // from the source
+    let (start, end) = ((-dst).max(0), len.min(dst_len - dst));
+    let (start, end) = match flip {
+        true => (start.max(src + len - src_len), end.min(src + len)),
+        false => (start.max(-src), end.min(src_len - src)),
+    };
+
+    (start..end.max(start)).map(move |i| {
+        let source = match flip {
+            true => src + len - 1 - i,
+            false => src + i,
+        };
+        ((dst + i) as i32, source as u32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlitOptions;
+    use crate::blend::BlendMode;
+    use crate::draw::Rect;
+    use crate::render::test_util::rgba_at;
+    use crate::render::{Pixel, PixelBuffer};
+
+    /// 3x2 sprite where the red channel is the flat index + 1, so no pixel
+    /// is black
+    fn sprite() -> PixelBuffer {
+        let mut sprite = PixelBuffer::new(3, 2);
+        for (x, y, p) in sprite.enumerate_mut() {
+            *p = Pixel::new((y * 3 + x + 1) as u8, 0, 0, 255);
+        }
+        sprite
+    }
+
+    /// The red channel of every pixel, row by row
+    fn red(buffer: &PixelBuffer) -> Vec<Vec<u8>> {
+        buffer
+            .rows()
+            .map(|row| row.iter().map(|p| p.r).collect())
+            .collect()
+    }
+
+    fn blit(dst_x: i32, dst_y: i32, options: BlitOptions) -> Vec<Vec<u8>> {
+        let mut buffer = PixelBuffer::new(4, 3);
+        let sprite = sprite();
+        buffer.blit(&sprite, sprite.bounds(), dst_x, dst_y, options);
+        red(&buffer)
+    }
+
+    #[test]
+    fn blits_inside() {
+        let expected = [[0, 0, 0, 0], [0, 1, 2, 3], [0, 4, 5, 6]];
+        assert_eq!(blit(1, 1, BlitOptions::default()), expected);
+    }
+
+    #[test]
+    fn clips_every_edge() {
+        let options = BlitOptions::default();
+        // Left and top, negative destinations
+        assert_eq!(blit(-2, -1, options), [[6, 0, 0, 0], [0; 4], [0; 4]]);
+        // Right and bottom
+        assert_eq!(blit(2, 2, options), [[0; 4], [0; 4], [0, 0, 1, 2]]);
+        // Entirely outside
+        assert_eq!(blit(4, 0, options), [[0; 4]; 3]);
+        assert_eq!(blit(0, -2, options), [[0; 4]; 3]);
+    }
+
+    #[test]
+    fn source_rect_is_clipped_to_the_source() {
+        let mut buffer = PixelBuffer::new(4, 3);
+        let rect = Rect::new(1, -1, 4, 2);
+        buffer.blit(&sprite(), rect, 0, 0, BlitOptions::default());
+        // The row above the sprite and the column past it are skipped
+        assert_eq!(red(&buffer), [[0; 4], [2, 3, 0, 0], [0; 4]]);
+    }
+
+    #[test]
+    fn extreme_coordinates_do_not_overflow() {
+        let options = BlitOptions::default();
+        assert_eq!(blit(i32::MIN, i32::MIN, options), [[0; 4]; 3]);
+        assert_eq!(blit(i32::MAX, i32::MAX, options), [[0; 4]; 3]);
+
+        let mut buffer = PixelBuffer::new(4, 3);
+        let sprite = sprite();
+        let huge = Rect::new(i32::MAX - 1, i32::MIN, u32::MAX, u32::MAX);
+        buffer.blit(&sprite, huge, 0, 0, options);
+        buffer.blit(&sprite, huge, i32::MIN, i32::MAX, options);
+        let flipped = BlitOptions {
+            flip_x: true,
+            flip_y: true,
+            ..options
+        };
+        buffer.blit(&sprite, huge, 0, 0, flipped);
+        assert_eq!(red(&buffer), [[0; 4]; 3]);
+
+        let everything = Rect::new(i32::MIN, i32::MIN, u32::MAX, u32::MAX);
+        // Source (0, 0) lands on (0, 0)
+        buffer.blit(&sprite, everything, i32::MIN, i32::MIN, options);
+        assert_eq!(red(&buffer), [[1, 2, 3, 0], [4, 5, 6, 0], [0; 4]]);
+    }
+
+    #[test]
+    fn flips() {
+        let flip_x = BlitOptions {
+            flip_x: true,
+            ..BlitOptions::default()
+        };
+        assert_eq!(blit(0, 0, flip_x), [[3, 2, 1, 0], [6, 5, 4, 0], [0; 4]]);
+
+        let flip_y = BlitOptions {
+            flip_y: true,
+            ..BlitOptions::default()
+        };
+        assert_eq!(blit(0, 0, flip_y), [[4, 5, 6, 0], [1, 2, 3, 0], [0; 4]]);
+
+        // Clipping happens in the destination, after flipping
+        assert_eq!(blit(-1, 0, flip_x), [[2, 1, 0, 0], [5, 4, 0, 0], [0; 4]]);
+    }
+
+    #[test]
+    fn color_key_skips_pixels() {
+        let options = BlitOptions {
+            // Alpha doesn't matter
+            color_key: Some(Pixel::new(5, 0, 0, 0)),
+            ..BlitOptions::default()
+        };
+        assert_eq!(blit(0, 0, options), [[1, 2, 3, 0], [4, 0, 6, 0], [0; 4]]);
+    }
+
+    #[test]
+    fn alpha_threshold_skips_and_makes_opaque() {
+        let mut sprite = PixelBuffer::new(3, 1);
+        sprite.set(0, 0, Pixel::new(10, 0, 0, 20));
+        sprite.set(1, 0, Pixel::new(20, 0, 0, 128));
+        sprite.set(2, 0, Pixel::new(30, 0, 0, 255));
+
+        let options = BlitOptions {
+            blend_mode: BlendMode::SourceOver,
+            alpha_threshold: Some(128),
+            ..BlitOptions::default()
+        };
+        let mut buffer = PixelBuffer::new(3, 1);
+        buffer.fill(Pixel::new(99, 0, 0, 255));
+        buffer.blit(&sprite, sprite.bounds(), 0, 0, options);
+
+        let pixels = buffer.enumerate().map(|(_, _, p)| (p.r, p.a));
+        assert_eq!(
+            pixels.collect::<Vec<_>>(),
+            [(99, 255), (20, 255), (30, 255)]
+        );
+    }
+
+    #[test]
+    fn images_are_loaded() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/textures/supertexture.png");
+        let texture = PixelBuffer::open(path).unwrap();
+        assert_eq!((texture.width(), texture.height()), (256, 256));
+
+        assert_eq!(rgba_at(&texture, 100, 50), (82, 75, 36, 255));
+        // Transparent pixels keep their colour
+        assert_eq!(rgba_at(&texture, 0, 0), (0, 0, 0, 0));
+        assert_eq!(rgba_at(&texture, 128, 128), (82, 75, 36, 0));
+
+        assert!(PixelBuffer::open("textures/missing.png").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::error::RendererError;
+use crate::post::check_spirv;
+
+// -----------------------------------------------------------------------------
+//     - Compute shader -
+//     A compute shader that runs on the base layer every frame, before it
+//     is drawn. See `compute/life.comp` for the bindings it gets. The
+//     canvas is seen the way the texture stores it: `Pixel` values / 255
+//     for the 8 bit formats, linear floats for `Rgba16Float`.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct ComputeShader<'a> {
+    /// Compiled SPIR-V, e.g. `include_bytes!("compute/life.comp.spv")` or
+    /// one loaded at runtime. It's only read by `Renderer::add_compute`.
+    pub spirv: &'a [u8],
+    pub workgroups: Workgroups,
+    /// Initial contents of the uniform block at binding 2, can be updated
+    /// with `Renderer::set_compute_uniforms`
+    pub uniforms: Vec<u8>,
+    /// Sizes in bytes of storage buffers, bound from binding 3. They start
+    /// zeroed and keep their contents between frames.
+    pub buffers: Vec<u64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Workgroups {
+    /// Dispatch exactly this many, e.g. for shaders that work on their
+    /// buffers rather than the canvas
+    // The demo only runs per pixel shaders
+    #[allow(dead_code)]
+    Fixed(u32, u32, u32),
+    /// Enough workgroups of the shader's `local_size_x` and `local_size_y`
+    /// to cover every canvas pixel
+    PerPixel(u32, u32),
+}
+
+impl Workgroups {
+    fn count(self, (width, height): (u32, u32)) -> (u32, u32, u32) {
+        match self {
+            Workgroups::Fixed(x, y, z) => (x, y, z),
+            Workgroups::PerPixel(x, y) => (width.div_ceil(x.max(1)), height.div_ceil(y.max(1)), 1),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ComputeId(u32);
+
+// -----------------------------------------------------------------------------
+//     - Compute passes -
+//     Storage textures can't be sRGB, so the base layer is copied into one
+//     of two storage textures with the same bytes, every shader reads one
+//     and writes the other, and the result is copied back.
+// -----------------------------------------------------------------------------
+pub struct ComputePasses {
+    /// Storage texture version of the canvas format, `None` if there isn't
+    /// one
+    format: Option<wgpu::TextureFormat>,
+    passes: Vec<ComputePass>,
+    next_id: u32,
+    /// Created on the first frame and whenever the canvas is resized
+    targets: Option<[wgpu::Texture; 2]>,
+    size: (u32, u32),
+}
+
+struct ComputePass {
+    id: ComputeId,
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    uniforms: wgpu::Buffer,
+    uniforms_len: usize,
+    buffers: Vec<wgpu::Buffer>,
+    workgroups: Workgroups,
+}
+
+impl ComputePasses {
+    pub fn new(format: Option<wgpu::TextureFormat>) -> Self {
+        Self {
+            format,
+            passes: Vec::new(),
+            next_id: 0,
+            targets: None,
+            size: (0, 0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Runs after the shaders that were added before it
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &ComputeShader,
+    ) -> Result<ComputeId, RendererError> {
+        let format = self.format.ok_or(RendererError::StorageUnsupported)?;
+        check_spirv(shader.spirv)?;
+
+        let storage_entry = |binding, readonly| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+                format,
+                readonly,
+            },
+            count: None,
+        };
+
+        let mut entries = vec![
+            storage_entry(0, true),
+            storage_entry(1, false),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        entries.extend(
+            (0..shader.buffers.len()).map(|i| wgpu::BindGroupLayoutEntry {
+                binding: 3 + i as u32,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: false,
+                },
+                count: None,
+            }),
+        );
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("compute bind group layout"),
+            entries: &entries,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("compute pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let module = device.create_shader_module(wgpu::util::make_spirv(shader.spirv));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("compute pipeline"),
+            layout: Some(&pipeline_layout),
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: &module,
+                entry_point: "main",
+            },
+        });
+
+        // Bindings can't be empty
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("compute uniforms"),
+            size: padded(&shader.uniforms).len().max(16) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: true,
+        });
+        uniforms.slice(..).get_mapped_range_mut()[..shader.uniforms.len()]
+            .copy_from_slice(&shader.uniforms);
+        uniforms.unmap();
+
+        let buffers = shader
+            .buffers
+            .iter()
+            .map(|&size| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("compute storage"),
+                    size: size.max(4).div_ceil(4) * 4,
+                    usage: wgpu::BufferUsage::STORAGE,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        let id = ComputeId(self.next_id);
+        self.next_id += 1;
+
+        self.passes.push(ComputePass {
+            id,
+            pipeline,
+            layout,
+            uniforms,
+            uniforms_len: shader.uniforms.len(),
+            buffers,
+            workgroups: shader.workgroups,
+        });
+
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: ComputeId) {
+        self.passes.retain(|pass| pass.id != id);
+    }
+
+    /// `uniforms` has to be the same size as the ones the shader was added
+    /// with
+    pub fn set_uniforms(
+        &self,
+        queue: &wgpu::Queue,
+        id: ComputeId,
+        uniforms: &[u8],
+    ) -> Result<(), RendererError> {
+        let pass = match self.passes.iter().find(|pass| pass.id == id) {
+            Some(pass) => pass,
+            None => return Ok(()),
+        };
+
+        if uniforms.len() != pass.uniforms_len {
+            return Err(RendererError::UniformSizeMismatch {
+                expected: pass.uniforms_len,
+                actual: uniforms.len(),
+            });
+        }
+
+        if !uniforms.is_empty() {
+            queue.write_buffer(&pass.uniforms, 0, &padded(uniforms));
+        }
+        Ok(())
+    }
+
+    /// Record the shaders, reading and then writing `canvas`. The canvas
+    /// needs `COPY_SRC` and `COPY_DST` usage.
+    pub fn run(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        canvas: &wgpu::Texture,
+        canvas_size: (u32, u32),
+    ) {
+        let format = match self.format {
+            Some(format) if !self.passes.is_empty() => format,
+            _ => return,
+        };
+
+        if self.targets.is_none() || self.size != canvas_size {
+            self.size = canvas_size;
+            self.targets = Some([
+                create_target(device, format, canvas_size),
+                create_target(device, format, canvas_size),
+            ]);
+        }
+
+        let targets = match &self.targets {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        copy_texture(encoder, canvas, &targets[0], canvas_size);
+
+        for (i, pass) in self.passes.iter().enumerate() {
+            let (source, target) = (&targets[i % 2], &targets[(i + 1) % 2]);
+
+            // Pixels the shader doesn't write stay as they were
+            copy_texture(encoder, source, target, canvas_size);
+
+            let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+            let mut entries = vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&target_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(pass.uniforms.slice(..)),
+                },
+            ];
+            entries.extend(pass.buffers.iter().enumerate().map(|(i, buffer)| {
+                wgpu::BindGroupEntry {
+                    binding: 3 + i as u32,
+                    resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+                }
+            }));
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("compute bind group"),
+                layout: &pass.layout,
+                entries: &entries,
+            });
+
+            let (x, y, z) = pass.workgroups.count(canvas_size);
+            let mut compute_pass = encoder.begin_compute_pass();
+            compute_pass.set_pipeline(&pass.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch(x, y, z);
+        }
+
+        copy_texture(
+            encoder,
+            &targets[self.passes.len() % 2],
+            canvas,
+            canvas_size,
+        );
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    (width, height): (u32, u32),
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("compute target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsage::STORAGE
+            | wgpu::TextureUsage::COPY_SRC
+            | wgpu::TextureUsage::COPY_DST,
+    })
+}
+
+/// Level 0 only. The formats can differ as long as their texels are the
+/// same size, e.g. `Rgba8UnormSrgb` and `Rgba8Unorm`.
+fn copy_texture(
+    encoder: &mut wgpu::CommandEncoder,
+    source: &wgpu::Texture,
+    destination: &wgpu::Texture,
+    (width, height): (u32, u32),
+) {
+    let copy_view = |texture| wgpu::TextureCopyView {
+        texture,
+        mip_level: 0,
+        origin: wgpu::Origin3d::ZERO,
+    };
+
+    encoder.copy_texture_to_texture(
+        copy_view(source),
+        copy_view(destination),
+        wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+    );
+}
+
+/// Buffer writes have to be a multiple of 4 bytes, uniform blocks are a
+/// multiple of 16 anyway
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().div_ceil(16) * 16, 0);
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{padded, Workgroups};
+
+    #[test]
+    fn per_pixel_covers_the_canvas() {
+        assert_eq!(Workgroups::PerPixel(8, 8).count((128, 128)), (16, 16, 1));
+        assert_eq!(Workgroups::PerPixel(8, 8).count((129, 1)), (17, 1, 1));
+        assert_eq!(Workgroups::PerPixel(0, 4).count((3, 3)), (3, 1, 1));
+        assert_eq!(Workgroups::Fixed(2, 3, 4).count((128, 128)), (2, 3, 4));
+    }
+
+    #[test]
+    fn uniforms_are_padded_to_16() {
+        assert_eq!(padded(&[]).len(), 0);
+        assert_eq!(padded(&[1; 4]), [&[1; 4][..], &[0; 12]].concat());
+        assert_eq!(padded(&[1; 16]).len(), 16);
+        assert_eq!(padded(&[1; 17]).len(), 32);
+    }
+}
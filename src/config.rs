@@ -0,0 +1,227 @@
+use std::env;
+
+use crate::adapter::{parse_backends, parse_power_preference};
+use crate::filter::{parse_filter, FilterMode};
+use crate::format::CanvasFormat;
+use crate::scaling::{parse_scaling_mode, ScalingMode};
+
+/// Comma separated backends, e.g. "vulkan" or "dx12,dx11"
+pub const BACKEND_ENV: &str = "WGPU_BACKEND";
+/// "low", "high" or "default"
+pub const POWER_PREFERENCE_ENV: &str = "WGPU_POWER_PREF";
+/// Part of the adapter name, e.g. "intel"
+pub const ADAPTER_NAME_ENV: &str = "WGPU_ADAPTER_NAME";
+/// "1" lists the adapters instead of running, see `adapter::list_adapters`
+pub const LIST_ADAPTERS_ENV: &str = "WGPU_LIST_ADAPTERS";
+/// "nearest", "bilinear", "trilinear" or "sharp"
+pub const FILTER_ENV: &str = "CANVAS_FILTER";
+/// "stretch", "fit", "integer" or "fill"
+pub const SCALING_ENV: &str = "CANVAS_SCALING";
+
+// -----------------------------------------------------------------------------
+//     - Renderer config -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Fifo is vsync. Mailbox and Immediate don't wait for it, if the
+    /// surface doesn't support them wgpu falls back to Fifo.
+    pub present_mode: wgpu::PresentMode,
+    /// Frames per second to aim for, `None` renders as often as the
+    /// present mode allows
+    pub target_fps: Option<u32>,
+    /// Backends to look for adapters on
+    pub backends: wgpu::BackendBit,
+    pub power_preference: wgpu::PowerPreference,
+    /// Use the first adapter with this in its name (ignoring case) instead
+    /// of letting wgpu choose
+    pub adapter_name: Option<String>,
+    /// Texture format of the layers, and so what `Pixel` values mean
+    pub canvas_format: CanvasFormat,
+    /// How layers are sampled, can be changed later with
+    /// `Renderer::set_filter`
+    pub filter: FilterMode,
+    /// How the canvas is fitted into the window, can be changed later with
+    /// `Renderer::set_scaling_mode`
+    pub scaling_mode: ScalingMode,
+    /// Recompile shaders when their source changes, see
+    /// `Renderer::reload_shaders`. Needs the `hot-reload` feature and the
+    /// source tree, ignored without them.
+    pub hot_reload: bool,
+    /// Frame rate recordings are stamped with, see `Renderer::start_recording`
+    pub record_fps: u32,
+    /// Seconds of frames to keep in memory for `Renderer::save_recent`, 0
+    /// keeps none
+    pub record_history: u32,
+}
+
+impl RendererConfig {
+    /// The default config, with the adapter settings overridden by
+    /// `WGPU_BACKEND`, `WGPU_POWER_PREF` and `WGPU_ADAPTER_NAME`, the
+    /// filter by `CANVAS_FILTER` and the scaling mode by `CANVAS_SCALING`,
+    /// where set.
+    /// Values that don't parse are ignored with a warning.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = env::var(BACKEND_ENV) {
+            match parse_backends(&value) {
+                Some(backends) => config.backends = backends,
+                None => eprintln!("ignoring {}={:?}", BACKEND_ENV, value),
+            }
+        }
+
+        if let Ok(value) = env::var(POWER_PREFERENCE_ENV) {
+            match parse_power_preference(&value) {
+                Some(preference) => config.power_preference = preference,
+                None => eprintln!("ignoring {}={:?}", POWER_PREFERENCE_ENV, value),
+            }
+        }
+
+        if let Ok(value) = env::var(ADAPTER_NAME_ENV) {
+            config.adapter_name = Some(value).filter(|name| !name.is_empty());
+        }
+
+        if let Ok(value) = env::var(FILTER_ENV) {
+            match parse_filter(&value) {
+                Some(filter) => config.filter = filter,
+                None => eprintln!("ignoring {}={:?}", FILTER_ENV, value),
+            }
+        }
+
+        if let Ok(value) = env::var(SCALING_ENV) {
+            match parse_scaling_mode(&value) {
+                Some(mode) => config.scaling_mode = mode,
+                None => eprintln!("ignoring {}={:?}", SCALING_ENV, value),
+            }
+        }
+
+        config
+    }
+}
+
+/// True if `WGPU_LIST_ADAPTERS` asks for a list of the adapters
+pub fn list_adapters_from_env() -> bool {
+    env::var(LIST_ADAPTERS_ENV).is_ok_and(|value| value.trim() == "1")
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Fifo,
+            target_fps: None,
+            backends: wgpu::BackendBit::PRIMARY,
+            power_preference: wgpu::PowerPreference::Default,
+            adapter_name: None,
+            canvas_format: CanvasFormat::default(),
+            filter: FilterMode::default(),
+            scaling_mode: ScalingMode::default(),
+            hot_reload: false,
+            record_fps: 60,
+            record_history: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const ALL_ENV: [&str; 6] = [
+        BACKEND_ENV,
+        POWER_PREFERENCE_ENV,
+        ADAPTER_NAME_ENV,
+        LIST_ADAPTERS_ENV,
+        FILTER_ENV,
+        SCALING_ENV,
+    ];
+
+    // The environment is shared by every test thread
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Run `f` with only `vars` set out of the variables read here
+    fn with_env(vars: &[(&str, &str)], f: impl FnOnce()) {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for name in &ALL_ENV {
+            env::remove_var(name);
+        }
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        f();
+        for name in &ALL_ENV {
+            env::remove_var(name);
+        }
+    }
+
+    #[test]
+    fn unset_env_is_the_default() {
+        with_env(&[], || {
+            let config = RendererConfig::from_env();
+            let default = RendererConfig::default();
+            assert_eq!(config.backends, default.backends);
+            assert_eq!(config.power_preference, default.power_preference);
+            assert_eq!(config.adapter_name, None);
+            assert_eq!(config.filter, default.filter);
+            assert_eq!(config.scaling_mode, default.scaling_mode);
+        });
+    }
+
+    #[test]
+    fn env_overrides_the_default() {
+        let vars = [
+            (BACKEND_ENV, "vulkan,dx12"),
+            (POWER_PREFERENCE_ENV, "low"),
+            (ADAPTER_NAME_ENV, "intel"),
+            (FILTER_ENV, "sharp"),
+            (SCALING_ENV, "integer"),
+        ];
+        with_env(&vars, || {
+            let config = RendererConfig::from_env();
+            assert_eq!(
+                config.backends,
+                wgpu::BackendBit::VULKAN | wgpu::BackendBit::DX12
+            );
+            assert_eq!(config.power_preference, wgpu::PowerPreference::LowPower);
+            assert_eq!(config.adapter_name.as_deref(), Some("intel"));
+            assert_eq!(config.filter, FilterMode::SharpBilinear);
+            assert_eq!(config.scaling_mode, ScalingMode::Integer);
+        });
+    }
+
+    #[test]
+    fn bad_env_is_ignored() {
+        let vars = [
+            (BACKEND_ENV, "vulkan,glide"),
+            (POWER_PREFERENCE_ENV, "max"),
+            (ADAPTER_NAME_ENV, ""),
+            (FILTER_ENV, "cubic"),
+            (SCALING_ENV, "zoom"),
+        ];
+        with_env(&vars, || {
+            let config = RendererConfig::from_env();
+            let default = RendererConfig::default();
+            assert_eq!(config.backends, default.backends);
+            assert_eq!(config.power_preference, default.power_preference);
+            assert_eq!(config.adapter_name, None);
+            assert_eq!(config.filter, default.filter);
+            assert_eq!(config.scaling_mode, default.scaling_mode);
+        });
+    }
+
+    #[test]
+    fn adapters_are_listed_for_1() {
+        with_env(&[], || assert!(!list_adapters_from_env()));
+        for value in &["1", " 1\n"] {
+            with_env(&[(LIST_ADAPTERS_ENV, value)], || {
+                assert!(list_adapters_from_env(), "{:?}", value)
+            });
+        }
+        for value in &["", "0", "yes", "11"] {
+            with_env(&[(LIST_ADAPTERS_ENV, value)], || {
+                assert!(!list_adapters_from_env(), "{:?}", value)
+            });
+        }
+    }
+}
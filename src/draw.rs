@@ -0,0 +1,1048 @@
+// Rasterizing API for code drawing into a canvas, the demo only uses a
+// little of it
+#![allow(dead_code)]
+
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::blend::BlendMode;
+use crate::render::{Pixel, PixelBuffer};
+
+// -----------------------------------------------------------------------------
+//     - Rect -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// One past the last column, `i32::MAX` if that doesn't fit
+    pub fn right(&self) -> i32 {
+        saturate(self.x as i64 + self.width as i64)
+    }
+
+    /// One past the last row, `i32::MAX` if that doesn't fit
+    pub fn bottom(&self) -> i32 {
+        saturate(self.y as i64 + self.height as i64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if right <= x || bottom <= y {
+            return None;
+        }
+
+        Some(Rect::new(x, y, distance(x, right), distance(y, bottom)))
+    }
+
+    /// Smallest rect covering both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(x, y, distance(x, right), distance(y, bottom))
+    }
+
+    /// True if the rects overlap or share an edge or corner
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+fn saturate(x: i64) -> i32 {
+    x.max(i32::MIN as i64).min(i32::MAX as i64) as i32
+}
+
+/// `end - start`, which always fits a u32 when `start <= end`
+fn distance(start: i32, end: i32) -> u32 {
+    (end as i64 - start as i64) as u32
+}
+
+// -----------------------------------------------------------------------------
+//     - Fill rule -
+//     Decides what is "inside" a self intersecting polygon
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FillRule {
+    EvenOdd,
+    NonZero,
+}
+
+// -----------------------------------------------------------------------------
+//     - Drawing -
+//     Everything here clips against the buffer, so it's fine to draw
+//     partially (or entirely) outside of it. Every pixel drawn is blended
+//     onto the buffer with the given mode.
+// -----------------------------------------------------------------------------
+impl PixelBuffer {
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    /// Blend a single pixel, clipped to the buffer.
+    pub fn plot(&mut self, x: i32, y: i32, color: Pixel, mode: BlendMode) {
+        if x < 0 || y < 0 {
+            return;
+        }
+        self.blend_pixel(x as u32, y as u32, color, mode);
+    }
+
+    /// Horizontal span from `x0` to `x1`, both inclusive.
+    fn span(&mut self, x0: i32, x1: i32, y: i32, color: Pixel, mode: BlendMode) {
+        if y < 0 || y >= self.height() as i32 {
+            return;
+        }
+
+        let (x0, x1) = (x0.min(x1).max(0), x0.max(x1).min(self.width() as i32 - 1));
+        for x in x0..=x1 {
+            self.plot(x, y, color, mode);
+        }
+    }
+
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Pixel, mode: BlendMode) {
+        // Lines can be billions of points long, only walk the part inside
+        let line = Line::new(x0, y0, x1, y1);
+        for i in line.clip(&self.bounds()) {
+            let (x, y) = line.point(i);
+            self.plot(x, y, color, mode);
+        }
+    }
+
+    pub fn draw_rect(&mut self, rect: Rect, color: Pixel, mode: BlendMode) {
+        if rect.is_empty() {
+            return;
+        }
+
+        let (left, top) = (rect.x, rect.y);
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+
+        self.span(left, right, top, color, mode);
+        if bottom != top {
+            self.span(left, right, bottom, color, mode);
+        }
+
+        // Sides without the corners, they belong to the spans above
+        let rows = (top + 1).max(0)..bottom.min(self.height() as i32);
+        for y in rows {
+            self.plot(left, y, color, mode);
+            if right != left {
+                self.plot(right, y, color, mode);
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Pixel, mode: BlendMode) {
+        let rect = match rect.intersect(&self.bounds()) {
+            Some(rect) => rect,
+            None => return,
+        };
+
+        for y in rect.y..rect.bottom() {
+            self.span(rect.x, rect.right() - 1, y, color, mode);
+        }
+    }
+
+    pub fn draw_circle(&mut self, cx: i32, cy: i32, radius: i32, color: Pixel, mode: BlendMode) {
+        self.draw_ellipse(cx, cy, radius, radius, color, mode);
+    }
+
+    pub fn fill_circle(&mut self, cx: i32, cy: i32, radius: i32, color: Pixel, mode: BlendMode) {
+        self.fill_ellipse(cx, cy, radius, radius, color, mode);
+    }
+
+    pub fn draw_ellipse(
+        &mut self,
+        cx: i32,
+        cy: i32,
+        rx: i32,
+        ry: i32,
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        if rx < 0 || ry < 0 {
+            return;
+        }
+
+        // The four quadrants share the points on the axes, plot those only
+        // once
+        let ellipse = Ellipse::new(rx, ry);
+        for (y, dy) in self.ellipse_rows(cy, ry) {
+            let (x0, x1) = ellipse.row(dy);
+            self.span(cx.saturating_add(x0), cx.saturating_add(x1), y, color, mode);
+            let x0 = x0.max(1);
+            if x1 >= x0 {
+                self.span(cx.saturating_sub(x1), cx.saturating_sub(x0), y, color, mode);
+            }
+        }
+    }
+
+    pub fn fill_ellipse(
+        &mut self,
+        cx: i32,
+        cy: i32,
+        rx: i32,
+        ry: i32,
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        if rx < 0 || ry < 0 {
+            return;
+        }
+
+        // The widest point of each row, mirrored
+        let ellipse = Ellipse::new(rx, ry);
+        for (y, dy) in self.ellipse_rows(cy, ry) {
+            let (_, dx) = ellipse.row(dy);
+            self.span(cx.saturating_sub(dx), cx.saturating_add(dx), y, color, mode);
+        }
+    }
+
+    /// The rows of the buffer an ellipse covers, with their distance from
+    /// the center
+    fn ellipse_rows(&self, cy: i32, ry: i32) -> impl Iterator<Item = (i32, i32)> {
+        let (cy, ry) = (cy as i64, ry as i64);
+        let top = (cy - ry).max(0);
+        let bottom = (cy + ry).min(self.height() as i64 - 1);
+        (top..=bottom).map(move |y| (y as i32, (y - cy).abs() as i32))
+    }
+
+    pub fn draw_triangle(
+        &mut self,
+        a: (i32, i32),
+        b: (i32, i32),
+        c: (i32, i32),
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        self.draw_polygon(&[a, b, c], color, mode);
+    }
+
+    pub fn fill_triangle(
+        &mut self,
+        a: (i32, i32),
+        b: (i32, i32),
+        c: (i32, i32),
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        self.fill_polygon(&[a, b, c], FillRule::NonZero, color, mode);
+    }
+
+    /// Closed outline through all the points.
+    pub fn draw_polygon(&mut self, points: &[(i32, i32)], color: Pixel, mode: BlendMode) {
+        match points {
+            [] => {}
+            [(x, y)] => self.plot(*x, *y, color, mode),
+            [(x0, y0), (x1, y1)] => self.draw_line(*x0, *y0, *x1, *y1, color, mode),
+            _ => {
+                // Skip the last point of every edge as it's the first point
+                // of the next one. Zero length edges have no points of their
+                // own, plotting their start would blend it twice.
+                let edges = points.iter().zip(points.iter().cycle().skip(1));
+                let mut plotted = false;
+                for (&(x0, y0), &(x1, y1)) in edges.filter(|(a, b)| a != b) {
+                    let line = Line::new(x0, y0, x1, y1);
+                    let len = line.steps() - 1;
+                    let steps = line.clip(&self.bounds());
+                    for i in steps.start..steps.end.min(len) {
+                        let (x, y) = line.point(i);
+                        self.plot(x, y, color, mode);
+                    }
+                    plotted = true;
+                }
+
+                // Every point is the same one
+                if !plotted {
+                    self.plot(points[0].0, points[0].1, color, mode);
+                }
+            }
+        }
+    }
+
+    /// Scanline fill. Points are pixel corners, and a pixel is filled when
+    /// its center is inside the polygon, so a polygon from (0, 0) to (4, 4)
+    /// covers the same pixels as `fill_rect(Rect::new(0, 0, 4, 4))`.
+    pub fn fill_polygon(
+        &mut self,
+        points: &[(i32, i32)],
+        rule: FillRule,
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let min_y = points.iter().map(|p| p.1).min().unwrap_or(0).max(0);
+        let max_y = points
+            .iter()
+            .map(|p| p.1)
+            .max()
+            .unwrap_or(0)
+            .min(self.height() as i32);
+
+        let edges = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .filter(|(a, b)| a.1 != b.1)
+            .collect::<Vec<_>>();
+
+        // (x, winding direction)
+        let mut crossings: Vec<(f64, i32)> = Vec::new();
+
+        for y in min_y..max_y {
+            let sample_y = y as f64 + 0.5;
+            crossings.clear();
+
+            for (&(x0, y0), &(x1, y1)) in &edges {
+                let (top, bottom) = (y0.min(y1) as f64, y0.max(y1) as f64);
+                if sample_y < top || sample_y >= bottom {
+                    continue;
+                }
+
+                let t = (sample_y - y0 as f64) / (y1 as f64 - y0 as f64);
+                let x = x0 as f64 + t * (x1 as f64 - x0 as f64);
+                let dir = if y1 > y0 { 1 } else { -1 };
+                crossings.push((x, dir));
+            }
+
+            crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                let (start, dir) = pair[0];
+                let end = pair[1].0;
+                winding += dir;
+
+                let inside = match rule {
+                    FillRule::EvenOdd => winding % 2 != 0,
+                    FillRule::NonZero => winding != 0,
+                };
+
+                if inside {
+                    // Pixel centers in [start, end)
+                    let x0 = (start - 0.5).ceil() as i32;
+                    let x1 = (end - 0.5).ceil() as i32 - 1;
+                    if x1 >= x0 {
+                        self.span(x0, x1, y, color, mode);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Bresenham -
+//     The point at any step is worked out directly rather than walked to,
+//     so a line can be clipped without visiting the part outside. Along
+//     the longer axis every step moves one pixel, along the shorter one
+//     the offset is rounded half up, which is what stepping with an error
+//     term gives. In i128, the products of two i32 differences don't fit
+//     an i64.
+// -----------------------------------------------------------------------------
+struct Line {
+    start: (i128, i128),
+    signs: (i128, i128),
+    /// Length along the longer and the shorter axis
+    major: i128,
+    minor: i128,
+    x_major: bool,
+}
+
+impl Line {
+    fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> Self {
+        let (x0, y0, x1, y1) = (x0 as i128, y0 as i128, x1 as i128, y1 as i128);
+        let (dx, dy) = ((x1 - x0).abs(), (y1 - y0).abs());
+        Self {
+            start: (x0, y0),
+            signs: ((x1 - x0).signum(), (y1 - y0).signum()),
+            major: dx.max(dy),
+            minor: dx.min(dy),
+            x_major: dx >= dy,
+        }
+    }
+
+    /// Number of points, both end points included
+    fn steps(&self) -> usize {
+        usize::try_from(self.major)
+            .unwrap_or(usize::MAX)
+            .saturating_add(1)
+    }
+
+    /// Always between the end points
+    fn point(&self, i: usize) -> (i32, i32) {
+        let along = i as i128;
+        let across = (2 * along * self.minor + self.major) / (2 * self.major).max(1);
+        let (dx, dy) = match self.x_major {
+            true => (along, across),
+            false => (across, along),
+        };
+        let (x, y) = (
+            self.start.0 + self.signs.0 * dx,
+            self.start.1 + self.signs.1 * dy,
+        );
+        (x as i32, y as i32)
+    }
+
+    /// The steps that land inside `bounds`. Both coordinates only ever
+    /// move one way, so those are a single range, found by bisecting.
+    fn clip(&self, bounds: &Rect) -> Range<usize> {
+        let (left, right) = (bounds.x as i128, bounds.right() as i128 - 1);
+        let (top, bottom) = (bounds.y as i128, bounds.bottom() as i128 - 1);
+        let (sx, sy) = (self.signs.0.max(0) * 2 - 1, self.signs.1.max(0) * 2 - 1);
+
+        // Coordinates flipped so they go up with every step
+        let (first_x, last_x) = if sx > 0 { (left, right) } else { (right, left) };
+        let (first_y, last_y) = if sy > 0 { (top, bottom) } else { (bottom, top) };
+        let flipped = |i| {
+            let (x, y) = self.point(i);
+            (sx * x as i128, sy * y as i128)
+        };
+
+        let start = partition_point(self.steps(), |i| {
+            let (x, y) = flipped(i);
+            x < sx * first_x || y < sy * first_y
+        });
+        let end = partition_point(self.steps(), |i| {
+            let (x, y) = flipped(i);
+            x <= sx * last_x && y <= sy * last_y
+        });
+
+        start..end.max(start)
+    }
+}
+
+/// The first of `0..len` where `pred` is false, `pred` has to be true up
+/// to some point and false after it
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match pred(mid) {
+            true => low = mid + 1,
+            false => high = mid,
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+fn line_points(x0: i32, y0: i32, x1: i32, y1: i32) -> impl ExactSizeIterator<Item = (i32, i32)> {
+    let line = Line::new(x0, y0, x1, y1);
+    (0..line.steps()).map(move |i| line.point(i))
+}
+
+// -----------------------------------------------------------------------------
+//     - Midpoint ellipse -
+//     The points of the bottom right quadrant, relative to the center, are
+//     worked out a row at a time rather than walked to, so only the rows
+//     inside the buffer are visited. Circles are just ellipses where
+//     rx == ry. Like the midpoint algorithm, the shallow part at the top
+//     takes the nearest row for every column, and the steep part from
+//     `turn` down the nearest column for every row. Either one only ever
+//     moves a pixel at a time, so around the turn, where the curve can
+//     move faster than that, it lags behind the nearest pixel. That stretch
+//     is walked. Positions are doubled to keep the midpoints whole numbers,
+//     and the decision values of an i32 radius need more than 64 bits.
+// -----------------------------------------------------------------------------
+struct Ellipse {
+    rx: i128,
+    ry: i128,
+    /// Points of the shallow part from `walked_from` on, where they can lag
+    shallow_tail: Vec<(i128, i128)>,
+    walked_from: i128,
+    /// Columns of the first rows of the steep part, from `turn` down
+    steep_head: Vec<i128>,
+    /// First point of the steep part
+    turn: (i128, i128),
+}
+
+/// Far enough before the turn that the shallow part doesn't lag yet, and
+/// far enough after it that the steep part has caught up
+const ELLIPSE_WALK: i128 = 4;
+
+impl Ellipse {
+    fn new(rx: i32, ry: i32) -> Self {
+        let (rx, ry) = (rx as i128, ry as i128);
+        let mut ellipse = Self {
+            rx,
+            ry,
+            shallow_tail: Vec::new(),
+            walked_from: 0,
+            steep_head: Vec::new(),
+            turn: (0, 0),
+        };
+        if rx == 0 || ry == 0 {
+            return ellipse;
+        }
+
+        // Roughly where the slope gets steeper than -1
+        let turn = partition_point(rx as usize + 1, |x| {
+            let x = x as i128;
+            ry * ry * x < rx * rx * ellipse.shallow_row(x)
+        }) as i128;
+
+        let mut x = (turn - ELLIPSE_WALK).max(0);
+        let mut y = ellipse.shallow_row(x);
+        ellipse.walked_from = x;
+        while ry * ry * x < rx * rx * y {
+            ellipse.shallow_tail.push((x, y));
+            if ellipse.decision(2 * (x + 1), 2 * y - 1) >= 0 {
+                y -= 1;
+            }
+            x += 1;
+        }
+        ellipse.turn = (x, y);
+
+        for _ in 0..ELLIPSE_WALK.min(y + 1) {
+            ellipse.steep_head.push(x);
+            if ellipse.decision(2 * x + 1, 2 * (y - 1)) <= 0 {
+                x += 1;
+            }
+            y -= 1;
+        }
+
+        ellipse
+    }
+
+    /// Negative inside the ellipse, for a point at (x / 2, y / 2)
+    fn decision(&self, x: i128, y: i128) -> i128 {
+        let (rx2, ry2) = (self.rx * self.rx, self.ry * self.ry);
+        ry2 * x * x + rx2 * y * y - 4 * rx2 * ry2
+    }
+
+    /// The lowest row whose midpoint with the row above is inside
+    fn shallow_row(&self, x: i128) -> i128 {
+        let end = partition_point(self.ry as usize + 1, |y| {
+            self.decision(2 * x, 2 * y as i128 - 1) < 0
+        });
+        (end as i128 - 1).max(0)
+    }
+
+    /// The rightmost column whose midpoint with the column to the left is
+    /// inside or on the ellipse
+    fn steep_column(&self, y: i128) -> i128 {
+        let end = partition_point(self.rx as usize + 1, |x| {
+            self.decision(2 * x as i128 - 1, 2 * y) <= 0
+        });
+        (end as i128 - 1).max(0)
+    }
+
+    /// The last column with a point in row `dy`, -1 above the ellipse
+    fn last_column(&self, dy: i128) -> i128 {
+        let (turn_x, turn_y) = self.turn;
+        if dy > self.ry {
+            return -1;
+        }
+        if dy < turn_y {
+            let i = (turn_y - dy) as usize;
+            return match self.steep_head.get(i) {
+                Some(&x) => x,
+                None => {
+                    let caught_up = self.steep_head.last().copied().unwrap_or(turn_x);
+                    self.steep_column(dy).max(caught_up)
+                }
+            };
+        }
+        if dy == turn_y {
+            return turn_x;
+        }
+        match self.shallow_tail.iter().rev().find(|p| p.1 >= dy) {
+            Some(&(x, y)) if y == dy => x,
+            // Rows the walk started below
+            _ => {
+                let walked_from = self.walked_from as usize;
+                partition_point(walked_from, |x| self.shallow_row(x as i128) >= dy) as i128 - 1
+            }
+        }
+    }
+
+    /// First and last column of the points in row `dy`, which is between
+    /// 0 and ry
+    fn row(&self, dy: i32) -> (i32, i32) {
+        if self.rx == 0 || self.ry == 0 {
+            return (0, self.rx as i32);
+        }
+
+        let dy = dy as i128;
+        let last = self.last_column(dy);
+        // Every step of the shallow part moves right, the steep part has a
+        // single point per row
+        let first = match dy < self.turn.1 {
+            true => last,
+            false => self.last_column(dy + 1) + 1,
+        };
+        (first as i32, last as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Pixel = Pixel {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    fn is_set(buffer: &PixelBuffer, x: i32, y: i32) -> bool {
+        buffer.get(x as u32, y as u32).is_some_and(|p| p.r == 255)
+    }
+
+    fn set_pixels(buffer: &PixelBuffer) -> Vec<(i32, i32)> {
+        buffer
+            .enumerate()
+            .filter(|(_, _, p)| p.r == 255)
+            .map(|(x, y, _)| (x as i32, y as i32))
+            .collect()
+    }
+
+    #[test]
+    fn line_covers_end_points_in_every_octant() {
+        let ends = [
+            (15, 12),
+            (12, 15),
+            (8, 15),
+            (5, 12),
+            (5, 8),
+            (8, 5),
+            (12, 5),
+            (15, 8),
+        ];
+
+        for &(x1, y1) in &ends {
+            let mut buffer = PixelBuffer::new(20, 20);
+            buffer.draw_line(10, 10, x1, y1, WHITE, BlendMode::Replace);
+
+            assert!(is_set(&buffer, 10, 10));
+            assert!(is_set(&buffer, x1, y1));
+
+            // One pixel per step along the longer axis, each touching the
+            // one before
+            let steps = (x1 - 10).abs().max((y1 - 10).abs()) as usize + 1;
+            assert_eq!(set_pixels(&buffer).len(), steps);
+
+            let points = line_points(10, 10, x1, y1).collect::<Vec<_>>();
+            for pair in points.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert!((a.0 - b.0).abs() <= 1 && (a.1 - b.1).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn line_points_do_not_overflow() {
+        let points = line_points(i32::MIN, i32::MIN, i32::MAX, i32::MAX);
+        assert_eq!(points.len(), 1 << 32);
+
+        let mut points = line_points(i32::MAX, 0, i32::MIN, 0);
+        assert_eq!(points.next(), Some((i32::MAX, 0)));
+        assert_eq!(points.next(), Some((i32::MAX - 1, 0)));
+
+        // Entirely outside, returns without walking the line
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_line(i32::MIN, i32::MIN, i32::MAX, -1, WHITE, BlendMode::Replace);
+        assert!(set_pixels(&buffer).is_empty());
+    }
+
+    /// Bresenham with an error term, what `Line` has to agree with
+    fn walk(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<(i32, i32)> {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        let mut points = vec![(x, y)];
+        while (x, y) != (x1, y1) {
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+            points.push((x, y));
+        }
+        points
+    }
+
+    /// The midpoint ellipse walk, what `Ellipse` has to agree with. Calls
+    /// `f` with every point of the bottom right quadrant.
+    fn walk_ellipse(rx: i32, ry: i32, mut f: impl FnMut(i32, i32)) {
+        if rx == 0 || ry == 0 {
+            for x in 0..=rx {
+                for y in 0..=ry {
+                    f(x, y);
+                }
+            }
+            return;
+        }
+
+        let (rx2, ry2) = (rx as i64 * rx as i64, ry as i64 * ry as i64);
+        let (mut x, mut y) = (0, ry as i64);
+        let (mut px, mut py) = (0, 2 * rx2 * y);
+
+        // Region 1: slope is shallower than -1
+        let mut p = 4 * ry2 - 4 * rx2 * ry as i64 + rx2;
+        while px < py {
+            f(x as i32, y as i32);
+            x += 1;
+            px += 2 * ry2;
+            if p < 0 {
+                p += 4 * (ry2 + px);
+            } else {
+                y -= 1;
+                py -= 2 * rx2;
+                p += 4 * (ry2 + px - py);
+            }
+        }
+
+        // Region 2: the rest, down to the x axis
+        let mut p = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+        while y >= 0 {
+            f(x as i32, y as i32);
+            y -= 1;
+            py -= 2 * rx2;
+            if p > 0 {
+                p += 4 * (rx2 - py);
+            } else {
+                x += 1;
+                px += 2 * ry2;
+                p += 4 * (rx2 - py + px);
+            }
+        }
+    }
+
+    #[test]
+    fn ellipse_rows_match_the_midpoint_walk() {
+        for rx in 0..100 {
+            for ry in 0..100 {
+                let mut rows = vec![Vec::new(); ry as usize + 1];
+                walk_ellipse(rx, ry, |x, y| rows[y as usize].push(x));
+
+                let ellipse = Ellipse::new(rx, ry);
+                for (dy, xs) in rows.iter().enumerate() {
+                    let (x0, x1) = ellipse.row(dy as i32);
+                    let expected = (xs[0], xs[xs.len() - 1]);
+                    assert_eq!((x0, x1), expected, "rx {} ry {} row {}", rx, ry, dy);
+                    assert_eq!(xs.len(), (x1 - x0 + 1) as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn line_points_match_bresenham() {
+        for x1 in -9..=9 {
+            for y1 in -9..=9 {
+                let points = line_points(0, 0, x1, y1).collect::<Vec<_>>();
+                assert_eq!(points, walk(0, 0, x1, y1), "to ({}, {})", x1, y1);
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_lines_match_unclipped() {
+        let ends = [-7, -2, 0, 3, 5, 6, 11];
+        for &x0 in &ends {
+            for &y0 in &ends {
+                for &x1 in &ends {
+                    for &y1 in &ends {
+                        let mut buffer = PixelBuffer::new(6, 4);
+                        buffer.draw_line(x0, y0, x1, y1, WHITE, BlendMode::Replace);
+
+                        let mut expected = walk(x0, y0, x1, y1)
+                            .into_iter()
+                            .filter(|&(x, y)| buffer.bounds().contains(x, y))
+                            .collect::<Vec<_>>();
+                        expected.sort_by_key(|&(x, y)| (y, x));
+                        expected.dedup();
+                        let line = (x0, y0, x1, y1);
+                        assert_eq!(set_pixels(&buffer), expected, "{:?}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_shapes_only_touch_the_buffer() {
+        // Each of these would take billions of steps without clipping
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_line(i32::MIN, 1, i32::MAX, 1, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&buffer), [(0, 1), (1, 1), (2, 1), (3, 1)]);
+
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_line(
+            i32::MIN,
+            i32::MIN,
+            i32::MAX,
+            i32::MAX,
+            WHITE,
+            BlendMode::Replace,
+        );
+        assert_eq!(set_pixels(&buffer), [(0, 0), (1, 1), (2, 2), (3, 3)]);
+
+        let mut buffer = PixelBuffer::new(4, 4);
+        let tall = Rect::new(2, i32::MIN, 5, u32::MAX);
+        buffer.draw_rect(tall, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&buffer), [(2, 0), (2, 1), (2, 2), (2, 3)]);
+
+        let mut buffer = PixelBuffer::new(4, 4);
+        let points = [(1, i32::MIN), (1, i32::MAX), (i32::MAX, i32::MAX)];
+        buffer.draw_polygon(&points, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&buffer), [(1, 0), (1, 1), (1, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn huge_ellipses_only_touch_the_buffer() {
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_ellipse(1, 0, 1, i32::MAX, WHITE, BlendMode::Replace);
+        let sides = (0..4)
+            .flat_map(|y| vec![(0, y), (2, y)])
+            .collect::<Vec<_>>();
+        assert_eq!(set_pixels(&buffer), sides);
+
+        // The buffer is well inside
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_circle(0, 0, i32::MAX, WHITE, BlendMode::Replace);
+        assert!(set_pixels(&buffer).is_empty());
+        buffer.fill_circle(0, 0, i32::MAX, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&buffer).len(), 16);
+
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.fill_ellipse(i32::MIN, 2, i32::MAX, 0, WHITE, BlendMode::Replace);
+        assert!(set_pixels(&buffer).is_empty());
+        buffer.fill_ellipse(i32::MAX, 2, i32::MAX, 0, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&buffer), [(0, 2), (1, 2), (2, 2), (3, 2)]);
+    }
+
+    #[test]
+    fn circles_are_drawn() {
+        let mut outline = PixelBuffer::new(5, 5);
+        outline.draw_circle(2, 2, 2, WHITE, BlendMode::Replace);
+        let mut expected = vec![(1, 0), (2, 0), (3, 0)];
+        expected.extend((1..4).flat_map(|y| vec![(0, y), (4, y)]));
+        expected.extend(&[(1, 4), (2, 4), (3, 4)]);
+        assert_eq!(set_pixels(&outline), expected);
+
+        let mut filled = PixelBuffer::new(5, 5);
+        filled.fill_circle(2, 2, 2, WHITE, BlendMode::Replace);
+        let mut expected = vec![(1, 0), (2, 0), (3, 0)];
+        expected.extend((1..4).flat_map(|y| (0..5).map(move |x| (x, y))));
+        expected.extend(&[(1, 4), (2, 4), (3, 4)]);
+        assert_eq!(set_pixels(&filled), expected);
+
+        // A radius of 0 is the center
+        let mut dot = PixelBuffer::new(5, 5);
+        dot.draw_circle(2, 2, 0, WHITE, BlendMode::Replace);
+        dot.fill_circle(2, 2, -1, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&dot), [(2, 2)]);
+    }
+
+    #[test]
+    fn outlines_plot_every_pixel_once() {
+        // Blending twice would make some pixels lighter than the rest
+        let shapes: [fn(&mut PixelBuffer, Pixel); 5] = [
+            |b, c| b.draw_circle(4, 4, 3, c, BlendMode::Additive),
+            |b, c| b.draw_ellipse(4, 4, 4, 1, c, BlendMode::Additive),
+            |b, c| b.draw_triangle((0, 0), (8, 2), (3, 7), c, BlendMode::Additive),
+            // Zero length edges, including the closing one
+            |b, c| {
+                let points = [(0, 0), (8, 2), (8, 2), (3, 7), (0, 0)];
+                b.draw_polygon(&points, c, BlendMode::Additive)
+            },
+            |b, c| b.draw_polygon(&[(4, 4); 3], c, BlendMode::Additive),
+        ];
+
+        for draw in &shapes {
+            let mut buffer = PixelBuffer::new(9, 9);
+            draw(&mut buffer, Pixel::new(100, 100, 100, 255));
+            for (x, y, p) in buffer.enumerate() {
+                assert!(p.r == 0 || p.r == 100, "({}, {}) is {}", x, y, p.r);
+            }
+        }
+
+        let mut dot = PixelBuffer::new(9, 9);
+        dot.draw_polygon(&[(4, 4); 3], WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&dot), [(4, 4)]);
+    }
+
+    #[test]
+    fn triangles_are_drawn() {
+        let mut outline = PixelBuffer::new(5, 5);
+        outline.draw_triangle((0, 0), (4, 0), (0, 4), WHITE, BlendMode::Replace);
+        let expected = [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (4, 0),
+            (0, 1),
+            (3, 1),
+            (0, 2),
+            (2, 2),
+            (0, 3),
+            (1, 3),
+            (0, 4),
+        ];
+        assert_eq!(set_pixels(&outline), expected);
+
+        // Pixels with their centers inside, whichever way it's wound
+        for &(b, c) in &[((4, 0), (0, 4)), ((0, 4), (4, 0))] {
+            let mut filled = PixelBuffer::new(5, 5);
+            filled.fill_triangle((0, 0), b, c, WHITE, BlendMode::Replace);
+            let expected = [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)];
+            assert_eq!(set_pixels(&filled), expected);
+        }
+    }
+
+    #[test]
+    fn rect_edges_saturate() {
+        let rect = Rect::new(i32::MAX - 1, i32::MIN, u32::MAX, u32::MAX);
+        assert_eq!(rect.right(), i32::MAX);
+        assert_eq!(rect.bottom(), i32::MAX);
+
+        let everything = Rect::new(i32::MIN, i32::MIN, u32::MAX, u32::MAX);
+        let union = everything.union(&Rect::new(0, 0, 1, 1));
+        assert_eq!(union, Rect::new(i32::MIN, i32::MIN, u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn fill_rect_is_clipped() {
+        let mut buffer = PixelBuffer::new(8, 8);
+        buffer.fill_rect(Rect::new(-3, 5, 6, 10), WHITE, BlendMode::Replace);
+
+        let expected = (5..8)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        assert_eq!(set_pixels(&buffer), expected);
+
+        buffer.fill_rect(
+            Rect::new(i32::MAX - 1, 0, u32::MAX, 1),
+            WHITE,
+            BlendMode::Replace,
+        );
+        assert_eq!(set_pixels(&buffer).len(), expected.len());
+    }
+
+    #[test]
+    fn ellipses_are_symmetric() {
+        let (cx, cy) = (10, 10);
+
+        for &fill in &[false, true] {
+            let mut buffer = PixelBuffer::new(21, 21);
+            match fill {
+                true => buffer.fill_ellipse(cx, cy, 6, 3, WHITE, BlendMode::Replace),
+                false => buffer.draw_ellipse(cx, cy, 6, 3, WHITE, BlendMode::Replace),
+            }
+
+            for (x, y) in set_pixels(&buffer) {
+                assert!(is_set(&buffer, 2 * cx - x, y));
+                assert!(is_set(&buffer, x, 2 * cy - y));
+            }
+
+            for &(x, y) in &[(16, 10), (4, 10), (10, 13), (10, 7)] {
+                assert!(is_set(&buffer, x, y));
+            }
+            assert!(!is_set(&buffer, 17, 10));
+            assert!(!is_set(&buffer, 10, 14));
+        }
+    }
+
+    #[test]
+    fn fill_ellipse_far_outside_is_clipped() {
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.fill_ellipse(0, i32::MIN, 3, 3, WHITE, BlendMode::Replace);
+        buffer.fill_ellipse(i32::MAX, 2, 3, 3, WHITE, BlendMode::Replace);
+        assert!(set_pixels(&buffer).is_empty());
+    }
+
+    #[test]
+    fn fill_rules_differ_inside_overlaps() {
+        // A square with a smaller one inside, both wound the same way, so
+        // the inner one has a winding number of 2
+        let points = [
+            (0, 0),
+            (6, 0),
+            (6, 6),
+            (0, 6),
+            (0, 0),
+            (2, 2),
+            (4, 2),
+            (4, 4),
+            (2, 4),
+            (2, 2),
+        ];
+
+        let mut non_zero = PixelBuffer::new(6, 6);
+        non_zero.fill_polygon(&points, FillRule::NonZero, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&non_zero).len(), 36);
+
+        let mut even_odd = PixelBuffer::new(6, 6);
+        even_odd.fill_polygon(&points, FillRule::EvenOdd, WHITE, BlendMode::Replace);
+        assert_eq!(set_pixels(&even_odd).len(), 32);
+        for &(x, y) in &[(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert!(!is_set(&even_odd, x, y));
+        }
+    }
+
+    #[test]
+    fn fill_polygon_matches_fill_rect() {
+        let mut polygon = PixelBuffer::new(8, 8);
+        polygon.fill_polygon(
+            &[(1, 1), (5, 1), (5, 4), (1, 4)],
+            FillRule::NonZero,
+            WHITE,
+            BlendMode::Replace,
+        );
+
+        let mut rect = PixelBuffer::new(8, 8);
+        rect.fill_rect(Rect::new(1, 1, 4, 3), WHITE, BlendMode::Replace);
+
+        assert_eq!(set_pixels(&polygon), set_pixels(&rect));
+    }
+}
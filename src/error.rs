@@ -0,0 +1,141 @@
+use std::fmt;
+
+use crate::draw::Rect;
+
+// -----------------------------------------------------------------------------
+//     - Renderer error -
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum RendererError {
+    /// No adapter (gpu or software) that can draw to the surface
+    NoAdapter,
+    /// No adapter with this in its name
+    AdapterNotFound(String),
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The swap chain couldn't hand out a frame and it can't be recovered
+    /// from, i.e. out of memory. Timeouts skip the frame and an outdated or
+    /// lost swap chain is recreated, those aren't errors.
+    Surface(wgpu::SwapChainError),
+    /// Zero sized, or larger than `MAX_CANVAS_SIZE`
+    InvalidCanvasSize {
+        width: u32,
+        height: u32,
+    },
+    /// A headless output that's zero sized, or larger than
+    /// `MAX_TEXTURE_SIZE`
+    InvalidOutputSize {
+        width: u32,
+        height: u32,
+    },
+    /// Pixels that don't fit the texture they are uploaded to
+    UploadSizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// Uploading a part of the pixels that isn't inside them
+    UploadOutOfBounds {
+        rect: Rect,
+        size: (u32, u32),
+    },
+    /// The backend can't copy frames back to the cpu, e.g. a window
+    ReadbackUnsupported,
+    /// The backend can't run shaders, e.g. post processing
+    ShadersUnsupported,
+    /// Shader bytes that aren't a SPIR-V module
+    InvalidSpirv(&'static str),
+    /// Compute shaders can't write the canvas format
+    StorageUnsupported,
+    /// Uniforms that aren't the size the shader was set up with
+    UniformSizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    BufferMap(wgpu::BufferAsyncError),
+    /// Saving a screenshot or recorded frame failed
+    Image(image::ImageError),
+    /// Writing a recording failed
+    Io(std::io::Error),
+    /// A recorded frame that isn't the size of the stream
+    RecordSizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RendererError::NoAdapter => write!(f, "no suitable graphics adapter found"),
+            RendererError::AdapterNotFound(name) => write!(f, "no adapter named {:?}", name),
+            RendererError::RequestDevice(e) => write!(f, "failed to create device: {}", e),
+            RendererError::Surface(e) => write!(f, "surface error: {}", e),
+            RendererError::InvalidCanvasSize { width, height } => {
+                write!(f, "invalid canvas size {}x{}", width, height)
+            }
+            RendererError::InvalidOutputSize { width, height } => {
+                write!(f, "invalid output size {}x{}", width, height)
+            }
+            RendererError::UploadSizeMismatch { expected, actual } => write!(
+                f,
+                "uploading {}x{} pixels to a {}x{} texture",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            RendererError::UploadOutOfBounds { rect, size } => write!(
+                f,
+                "uploading {}x{} at ({}, {}) from {}x{} pixels",
+                rect.width, rect.height, rect.x, rect.y, size.0, size.1
+            ),
+            RendererError::ReadbackUnsupported => write!(f, "this backend can't read back frames"),
+            RendererError::ShadersUnsupported => write!(f, "this backend can't run shaders"),
+            RendererError::InvalidSpirv(reason) => write!(f, "invalid SPIR-V: {}", reason),
+            RendererError::StorageUnsupported => {
+                write!(f, "compute shaders can't write this canvas format")
+            }
+            RendererError::UniformSizeMismatch { expected, actual } => write!(
+                f,
+                "{} bytes of uniforms for a shader that takes {}",
+                actual, expected
+            ),
+            RendererError::BufferMap(e) => write!(f, "failed to map buffer: {}", e),
+            RendererError::Image(e) => write!(f, "failed to save image: {}", e),
+            RendererError::Io(e) => write!(f, "failed to write recording: {}", e),
+            RendererError::RecordSizeMismatch { expected, actual } => write!(
+                f,
+                "recording a {}x{} frame into a {}x{} stream",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+impl From<wgpu::RequestDeviceError> for RendererError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        RendererError::RequestDevice(e)
+    }
+}
+
+impl From<wgpu::SwapChainError> for RendererError {
+    fn from(e: wgpu::SwapChainError) -> Self {
+        RendererError::Surface(e)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for RendererError {
+    fn from(e: wgpu::BufferAsyncError) -> Self {
+        RendererError::BufferMap(e)
+    }
+}
+
+impl From<std::io::Error> for RendererError {
+    fn from(e: std::io::Error) -> Self {
+        RendererError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for RendererError {
+    fn from(e: image::ImageError) -> Self {
+        RendererError::Image(e)
+    }
+}
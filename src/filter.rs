@@ -0,0 +1,106 @@
+// -----------------------------------------------------------------------------
+//     - Filter mode -
+//     How layers are sampled when they are drawn larger or smaller than
+//     their size
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    /// Blocky, for pixel art at whole number scales
+    #[default]
+    Nearest,
+    /// Smooth, blurry when scaled up and grainy when scaled down a lot
+    Bilinear,
+    /// Bilinear between mipmaps, for photos and canvases drawn smaller
+    /// than their size
+    Trilinear,
+    /// Nearest with the edges between texels smoothed over one window
+    /// pixel, for pixel art at any scale
+    SharpBilinear,
+}
+
+impl FilterMode {
+    pub fn sampler_descriptor(self) -> wgpu::SamplerDescriptor<'static> {
+        let filter = match self {
+            FilterMode::Nearest => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        let mipmap_filter = match self {
+            FilterMode::Trilinear => wgpu::FilterMode::Linear,
+            _ => wgpu::FilterMode::Nearest,
+        };
+
+        wgpu::SamplerDescriptor {
+            label: Some("layer sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            ..Default::default()
+        }
+    }
+
+    /// Mip levels a texture of this size needs, 1 unless trilinear
+    pub fn mip_level_count(self, width: u32, height: u32) -> u32 {
+        match self {
+            FilterMode::Trilinear => 32 - width.max(height).max(1).leading_zeros(),
+            _ => 1,
+        }
+    }
+}
+
+/// Parse a filter name, e.g. for an environment variable. Case doesn't
+/// matter.
+pub fn parse_filter(s: &str) -> Option<FilterMode> {
+    match s.trim().to_lowercase().as_str() {
+        "nearest" | "point" => Some(FilterMode::Nearest),
+        "bilinear" | "linear" => Some(FilterMode::Bilinear),
+        "trilinear" => Some(FilterMode::Trilinear),
+        "sharp" | "sharp-bilinear" | "sharp_bilinear" => Some(FilterMode::SharpBilinear),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_are_parsed() {
+        assert_eq!(parse_filter("nearest"), Some(FilterMode::Nearest));
+        assert_eq!(parse_filter(" Point "), Some(FilterMode::Nearest));
+        assert_eq!(parse_filter("LINEAR"), Some(FilterMode::Bilinear));
+        assert_eq!(parse_filter("trilinear"), Some(FilterMode::Trilinear));
+        assert_eq!(
+            parse_filter("sharp_bilinear"),
+            Some(FilterMode::SharpBilinear)
+        );
+
+        for s in &["", "cubic", "nearest,linear", "sharp bilinear"] {
+            assert_eq!(parse_filter(s), None, "{:?}", s);
+        }
+    }
+    #[test]
+    fn mip_levels_go_down_to_1x1() {
+        let count = |w, h| FilterMode::Trilinear.mip_level_count(w, h);
+        assert_eq!(count(1, 1), 1);
+        assert_eq!(count(0, 0), 1);
+        assert_eq!(count(2, 2), 2);
+        // Non power of two sizes round down, 5x3 goes 5x3, 2x1, 1x1
+        assert_eq!(count(5, 3), 3);
+        assert_eq!(count(640, 480), 10);
+        // The longest side decides
+        assert_eq!(count(256, 1), 9);
+        assert_eq!(count(1, 257), 9);
+        assert_eq!(count(u32::MAX, 1), 32);
+
+        for &filter in &[
+            FilterMode::Nearest,
+            FilterMode::Bilinear,
+            FilterMode::SharpBilinear,
+        ] {
+            assert_eq!(filter.mip_level_count(640, 480), 1, "{:?}", filter);
+        }
+    }
+}
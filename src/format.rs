@@ -0,0 +1,316 @@
+use crate::blend::{linear_to_srgb, srgb_to_linear};
+use crate::draw::Rect;
+use crate::render::{Pixel, PixelBuffer};
+
+// -----------------------------------------------------------------------------
+//     - Canvas format -
+//     The texture format layers are uploaded as. This decides what a
+//     `Pixel` means: in the sRGB formats 128 is half as bright as it
+//     looks, in the linear format it's half the light, so it comes out
+//     brighter (188) on the sRGB swap chain.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+// Picked through `RendererConfig`, the demo keeps the default
+#[allow(dead_code)]
+pub enum CanvasFormat {
+    /// Pixels are sRGB encoded, what image files and colour pickers use
+    #[default]
+    Rgba8Srgb,
+    /// Pixels are linear intensities
+    Rgba8Linear,
+    /// Same as `Rgba8Srgb`, with red and blue swapped on upload
+    Bgra8Srgb,
+    /// Pixels are sRGB encoded and stored as linear half floats. They
+    /// still come from 8 bit `Pixel`s, so this gives more precision in the
+    /// darks but no values over 1 (it's not HDR).
+    Rgba16Float,
+}
+
+impl CanvasFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            CanvasFormat::Rgba8Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            CanvasFormat::Rgba8Linear => wgpu::TextureFormat::Rgba8Unorm,
+            CanvasFormat::Bgra8Srgb => wgpu::TextureFormat::Bgra8UnormSrgb,
+            CanvasFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+
+    /// What the shader gets when sampling, for the bind group layout.
+    /// Normalized and float formats both sample as floats.
+    pub fn component_type(self) -> wgpu::TextureComponentType {
+        match self {
+            CanvasFormat::Rgba8Srgb
+            | CanvasFormat::Rgba8Linear
+            | CanvasFormat::Bgra8Srgb
+            | CanvasFormat::Rgba16Float => wgpu::TextureComponentType::Float,
+        }
+    }
+
+    /// A format compute shaders can write with the same bytes per pixel,
+    /// `None` if there isn't one. The bytes are copied as they are, so
+    /// shaders see the `Pixel` values in the 8 bit formats and the linear
+    /// floats `encode` makes in `Rgba16Float`.
+    pub fn storage_format(self) -> Option<wgpu::TextureFormat> {
+        match self {
+            CanvasFormat::Rgba8Srgb | CanvasFormat::Rgba8Linear => {
+                Some(wgpu::TextureFormat::Rgba8Unorm)
+            }
+            CanvasFormat::Rgba16Float => Some(wgpu::TextureFormat::Rgba16Float),
+            // Storage textures can't be bgra
+            CanvasFormat::Bgra8Srgb => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            CanvasFormat::Rgba16Float => 8,
+            _ => 4,
+        }
+    }
+
+    /// The linear value the shader samples for a channel value.
+    pub fn decode(self, c: u8) -> f64 {
+        match self {
+            CanvasFormat::Rgba8Linear => c as f64 / 255.0,
+            // Rounded to what the half float `encode` stores can hold
+            CanvasFormat::Rgba16Float => f16_from_bits(f16_bits(srgb_to_linear(c) as f32)) as f64,
+            _ => srgb_to_linear(c),
+        }
+    }
+
+    /// `rect` of the pixels in this format, tightly packed. `None` if the
+    /// pixels can be uploaded as they are.
+    pub fn encode(self, pixels: &PixelBuffer, rect: Rect) -> Option<Vec<u8>> {
+        let rows = (rect.y as u32..rect.bottom() as u32).filter_map(|y| pixels.row(y));
+        let span = rect.x as usize..rect.right() as usize;
+        let mut bytes = Vec::with_capacity((rect.area() * self.bytes_per_pixel() as u64) as usize);
+
+        match self {
+            CanvasFormat::Rgba8Srgb | CanvasFormat::Rgba8Linear => return None,
+            CanvasFormat::Bgra8Srgb => {
+                for row in rows {
+                    for p in &row[span.clone()] {
+                        bytes.extend_from_slice(&[p.b, p.g, p.r, p.a]);
+                    }
+                }
+            }
+            CanvasFormat::Rgba16Float => {
+                for row in rows {
+                    for p in &row[span.clone()] {
+                        let channels = [
+                            srgb_to_linear(p.r),
+                            srgb_to_linear(p.g),
+                            srgb_to_linear(p.b),
+                            p.a as f64 / 255.0,
+                        ];
+                        for c in channels.iter() {
+                            bytes.extend_from_slice(&f16_bits(*c as f32).to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(bytes)
+    }
+
+    /// A pixel back from `bytes_per_pixel` bytes of a texture in this
+    /// format, the opposite of `encode`. Floats outside 0 - 1, e.g. from a
+    /// compute shader, are clamped.
+    pub fn read_pixel(self, bytes: &[u8]) -> Pixel {
+        match self {
+            CanvasFormat::Rgba8Srgb | CanvasFormat::Rgba8Linear => {
+                Pixel::new(bytes[0], bytes[1], bytes[2], bytes[3])
+            }
+            CanvasFormat::Bgra8Srgb => Pixel::new(bytes[2], bytes[1], bytes[0], bytes[3]),
+            CanvasFormat::Rgba16Float => {
+                let channel = |i: usize| {
+                    f16_from_bits(u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]])) as f64
+                };
+                let alpha = (channel(3).clamp(0.0, 1.0) * 255.0).round() as u8;
+                Pixel::new(
+                    linear_to_srgb(channel(0)),
+                    linear_to_srgb(channel(1)),
+                    linear_to_srgb(channel(2)),
+                    alpha,
+                )
+            }
+        }
+    }
+}
+
+/// `x` as an IEEE half float, rounded to nearest. NaN isn't handled, it
+/// can't come out of a `Pixel`.
+fn f16_bits(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = (bits >> 16) & 0x8000;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Too large, infinity
+    if exponent >= 31 {
+        return (sign | 0x7c00) as u16;
+    }
+
+    // Too small for a normal half float, subnormal or zero
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign as u16;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return (sign | ((mantissa >> shift) + round)) as u16;
+    }
+
+    // Rounding can carry into the exponent, which is still correct
+    let round = (mantissa >> 12) & 1;
+    (sign | (((exponent as u32) << 10 | mantissa >> 13) + round)) as u16
+}
+
+/// The IEEE half float `bits` as an `f32`, which holds every one exactly
+fn f16_from_bits(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    match exponent {
+        // Subnormal or zero
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{f16_bits, f16_from_bits, CanvasFormat};
+    use crate::config::RendererConfig;
+    use crate::draw::Rect;
+    use crate::render::test_util::rgba;
+    use crate::render::{Pixel, PixelBuffer, Renderer};
+
+    const FORMATS: [CanvasFormat; 4] = [
+        CanvasFormat::Rgba8Srgb,
+        CanvasFormat::Rgba8Linear,
+        CanvasFormat::Bgra8Srgb,
+        CanvasFormat::Rgba16Float,
+    ];
+
+    /// 256x1 buffer with every channel value, each channel in a different
+    /// order so they can't be mixed up
+    fn every_value() -> PixelBuffer {
+        let mut pixels = PixelBuffer::new(256, 1);
+        for (x, _, p) in pixels.enumerate_mut() {
+            let v = x as u8;
+            *p = Pixel::new(v, 255 - v, v.wrapping_add(85), v.wrapping_mul(3));
+        }
+        pixels
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let pixels = every_value();
+        let rect = Rect::new(0, 0, 256, 1);
+
+        for format in FORMATS.iter().copied() {
+            let bytes = format
+                .encode(&pixels, rect)
+                .unwrap_or_else(|| pixels.to_vec());
+            let bpp = format.bytes_per_pixel() as usize;
+            assert_eq!(bytes.len(), 256 * bpp, "{:?}", format);
+
+            for ((_, _, expected), texel) in pixels.enumerate().zip(bytes.chunks_exact(bpp)) {
+                let actual = format.read_pixel(texel);
+                assert_eq!(rgba(&actual), rgba(expected), "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn known_pixels_are_presented() {
+        // The window is sRGB, so a linear 128 is brighter than an sRGB one
+        let expected = [
+            (CanvasFormat::Rgba8Srgb, (128, 64, 200, 255)),
+            (CanvasFormat::Rgba8Linear, (188, 137, 229, 255)),
+            (CanvasFormat::Bgra8Srgb, (128, 64, 200, 255)),
+            (CanvasFormat::Rgba16Float, (128, 64, 200, 255)),
+        ];
+
+        for &(format, output) in expected.iter() {
+            let config = RendererConfig {
+                canvas_format: format,
+                ..RendererConfig::default()
+            };
+            let mut renderer = Renderer::software_with_config(1, 1, 1, 1, config).unwrap();
+            renderer.pixels().set(0, 0, Pixel::new(128, 64, 200, 255));
+            renderer.draw().unwrap();
+            renderer.render().unwrap();
+
+            let frame = renderer.read_frame().unwrap();
+            assert_eq!(rgba(frame.get(0, 0).unwrap()), output, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn half_floats_keep_every_srgb_value() {
+        // Rgba16Float has more precision than the 8 bit formats, nothing
+        // is lost on the way back
+        for c in 0..=255u8 {
+            let linear = CanvasFormat::Rgba16Float.decode(c);
+            assert_eq!(crate::blend::linear_to_srgb(linear), c);
+        }
+    }
+
+    #[test]
+    fn encode_only_takes_the_rect() {
+        let pixels = every_value();
+        let bytes = CanvasFormat::Bgra8Srgb
+            .encode(&pixels, Rect::new(10, 0, 2, 1))
+            .unwrap();
+        let p = pixels.get(10, 0).unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(bytes[..4], [p.b, p.g, p.r, p.a]);
+    }
+
+    #[test]
+    fn f16_known_bits() {
+        let cases = [
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (0.5, 0x3800),
+            (-2.0, 0xc000),
+            (65504.0, 0x7bff),
+            // Smallest normal and the largest and smallest subnormal
+            (2f32.powi(-14), 0x0400),
+            (1023.0 * 2f32.powi(-24), 0x03ff),
+            (2f32.powi(-24), 0x0001),
+        ];
+
+        for (value, bits) in cases.iter().copied() {
+            assert_eq!(f16_bits(value), bits, "{}", value);
+            assert_eq!(f16_from_bits(bits), value, "{:#06x}", bits);
+        }
+
+        // Out of range both ways
+        assert_eq!(f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f16_bits(2f32.powi(-26)), 0x0000);
+    }
+
+    #[test]
+    fn f16_rounds_to_nearest() {
+        // The step after 1.0 is 2^-10
+        let step = 2f32.powi(-10);
+        assert_eq!(f16_bits(1.0 + step * 0.4), 0x3c00);
+        assert_eq!(f16_bits(1.0 + step * 0.6), 0x3c01);
+        // Rounding up carries into the exponent
+        assert_eq!(f16_bits(2.0 - step * 0.1), 0x4000);
+        // And out of the subnormals
+        assert_eq!(f16_bits(2f32.powi(-14) - 2f32.powi(-26)), 0x0400);
+        assert_eq!(f16_from_bits(0x7c00), f32::INFINITY);
+        assert!(f16_from_bits(0x7e00).is_nan());
+    }
+}
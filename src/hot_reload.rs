@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// -----------------------------------------------------------------------------
+//     - Shader error -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct ShaderError {
+    pub path: PathBuf,
+    /// Line of the first error, if the compiler said
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderError {
+    fn new(path: &Path, message: String) -> Self {
+        // shaderc messages look like "path/shader.frag:12: error: ..."
+        let name = path.display().to_string();
+        let line = message
+            .lines()
+            .find_map(|l| l.find(&name).map(|i| &l[i + name.len()..]))
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|rest| rest.split(':').next())
+            .and_then(|line| line.trim().parse().ok());
+
+        Self {
+            path: path.to_owned(),
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Compiler messages already start every error with the path and line
+        if self.message.contains(&self.path.display().to_string()) {
+            return write!(f, "{}", self.message);
+        }
+
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// -----------------------------------------------------------------------------
+//     - Shader watcher -
+//     Compiles shaders from disk while the app runs and notices when they
+//     change. Files are polled, so call `changed` once a frame rather than
+//     in a loop.
+// -----------------------------------------------------------------------------
+pub struct ShaderWatcher {
+    compiler: shaderc::Compiler,
+    /// Modification time of every watched file as of the last `changed`
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl ShaderWatcher {
+    /// `None` if shaderc couldn't be loaded
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            compiler: shaderc::Compiler::new()?,
+            modified: HashMap::new(),
+        })
+    }
+
+    /// Start watching `path`, it only counts as changed once it's modified
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let modified = modified(&path);
+        self.modified.entry(path).or_insert(modified);
+    }
+
+    /// Watched files that were modified, created or deleted since the last
+    /// call
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        self.modified
+            .iter_mut()
+            .filter_map(|(path, last)| {
+                let now = modified(path);
+                match now != *last {
+                    true => {
+                        *last = now;
+                        Some(path.clone())
+                    }
+                    false => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Compile a `.vert`, `.frag` or `.comp` file to SPIR-V
+    pub fn compile(&mut self, path: &Path) -> Result<Vec<u32>, ShaderError> {
+        let kind = match path.extension().and_then(|e| e.to_str()) {
+            Some("vert") => shaderc::ShaderKind::Vertex,
+            Some("frag") => shaderc::ShaderKind::Fragment,
+            Some("comp") => shaderc::ShaderKind::Compute,
+            _ => {
+                let message = "not a .vert, .frag or .comp file".to_string();
+                return Err(ShaderError::new(path, message));
+            }
+        };
+
+        let src = fs::read_to_string(path).map_err(|e| ShaderError::new(path, e.to_string()))?;
+        let name = path.display().to_string();
+
+        self.compiler
+            .compile_into_spirv(&src, kind, &name, "main", None)
+            .map(|artifact| artifact.as_binary().to_vec())
+            .map_err(|e| ShaderError::new(path, e.to_string()))
+    }
+}
+
+/// Run `f`, turning a panic into an error for `path`. wgpu panics when a
+/// pipeline doesn't match its layout, which an edited shader easily can.
+pub fn catch_panic<T>(path: &Path, f: impl FnOnce() -> T) -> Result<T, ShaderError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => "rejected by wgpu".to_string(),
+            },
+        };
+        ShaderError::new(path, message)
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{catch_panic, ShaderError};
+
+    #[test]
+    fn line_comes_from_the_message() {
+        let path = Path::new("src/post/crt.frag");
+        let error = ShaderError::new(path, "src/post/crt.frag:12: error: 'x' : undeclared".into());
+        assert_eq!(error.line, Some(12));
+
+        let error = ShaderError::new(path, "compilation error:\nno line here".into());
+        assert_eq!(error.line, None);
+    }
+
+    #[test]
+    fn path_is_only_shown_once() {
+        let path = Path::new("src/post/crt.frag");
+        let message = "compilation error:\nsrc/post/crt.frag:12: error: 'x' : undeclared";
+        let error = ShaderError::new(path, message.into());
+        assert_eq!(error.to_string(), message);
+
+        let error = ShaderError::new(path, "file not found".into());
+        assert_eq!(error.to_string(), "src/post/crt.frag: file not found");
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let path = Path::new("src/shader.frag");
+        assert_eq!(catch_panic(path, || 1).unwrap(), 1);
+
+        let error = catch_panic(path, || panic!("interface mismatch {}", 1)).unwrap_err();
+        assert_eq!(error.path, path);
+        assert_eq!(error.message, "interface mismatch 1");
+    }
+}
@@ -0,0 +1,214 @@
+// Input API, the demo only follows the cursor and the left button
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use crate::render::Renderer;
+
+/// Pixel scroll deltas (touchpads) are turned into lines using this
+const PIXELS_PER_LINE: f64 = 20.0;
+
+// -----------------------------------------------------------------------------
+//     - Input state -
+//     Feed it every `WindowEvent`, read it while drawing, and call
+//     `end_frame` once the frame is done.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct InputState {
+    keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    /// In physical window pixels, `None` when the cursor is outside the
+    /// window
+    cursor: Option<PhysicalPosition<f64>>,
+    /// Lines scrolled since the last `end_frame`
+    scroll: (f32, f32),
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the event was an input event and has been recorded
+    pub fn update(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                match state {
+                    ElementState::Pressed => self.keys.insert(*key),
+                    ElementState::Released => self.keys.remove(key),
+                };
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                match state {
+                    ElementState::Pressed => self.buttons.insert(*button),
+                    ElementState::Released => self.buttons.remove(button),
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => self.cursor = Some(*position),
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+                    MouseScrollDelta::PixelDelta(pos) => (
+                        (pos.x / PIXELS_PER_LINE) as f32,
+                        (pos.y / PIXELS_PER_LINE) as f32,
+                    ),
+                };
+                self.scroll.0 += x;
+                self.scroll.1 += y;
+            }
+            // Releases that happen while unfocused never arrive, so forget
+            // everything rather than have keys stuck down
+            WindowEvent::Focused(false) => {
+                self.keys.clear();
+                self.buttons.clear();
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Reset the per frame state, i.e. the scroll delta
+    pub fn end_frame(&mut self) {
+        self.scroll = (0.0, 0.0);
+    }
+
+    pub fn key_held(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn button_held(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// Cursor position in physical window pixels
+    pub fn cursor(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor
+    }
+
+    /// The canvas pixel under the cursor, if any
+    pub fn canvas_cursor(&self, renderer: &Renderer) -> Option<(u32, u32)> {
+        renderer.window_to_canvas(self.cursor?)
+    }
+
+    /// Lines scrolled this frame, (horizontal, vertical)
+    pub fn scroll_delta(&self) -> (f32, f32) {
+        self.scroll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RendererConfig;
+    use crate::render::test_util::{button, cursor_moved, device, key};
+    use crate::scaling::ScalingMode;
+    use winit::event::ModifiersState;
+
+    #[test]
+    fn keys_are_held_between_press_and_release() {
+        let mut input = InputState::new();
+        assert!(!input.key_held(VirtualKeyCode::Space));
+
+        assert!(input.update(&key(VirtualKeyCode::Space, ElementState::Pressed)));
+        assert!(input.key_held(VirtualKeyCode::Space));
+        assert!(!input.key_held(VirtualKeyCode::A));
+
+        // Key repeat sends more presses, still held
+        input.update(&key(VirtualKeyCode::Space, ElementState::Pressed));
+        input.end_frame();
+        assert!(input.key_held(VirtualKeyCode::Space));
+
+        input.update(&key(VirtualKeyCode::Space, ElementState::Released));
+        assert!(!input.key_held(VirtualKeyCode::Space));
+    }
+
+    #[test]
+    fn buttons_are_held_between_press_and_release() {
+        let mut input = InputState::new();
+
+        input.update(&button(MouseButton::Left, ElementState::Pressed));
+        input.update(&button(MouseButton::Right, ElementState::Pressed));
+        assert!(input.button_held(MouseButton::Left));
+        assert!(input.button_held(MouseButton::Right));
+
+        input.update(&button(MouseButton::Left, ElementState::Released));
+        assert!(!input.button_held(MouseButton::Left));
+        assert!(input.button_held(MouseButton::Right));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = InputState::new();
+        input.update(&key(VirtualKeyCode::W, ElementState::Pressed));
+        input.update(&button(MouseButton::Left, ElementState::Pressed));
+
+        assert!(input.update(&WindowEvent::Focused(false)));
+        assert!(!input.key_held(VirtualKeyCode::W));
+        assert!(!input.button_held(MouseButton::Left));
+    }
+
+    #[test]
+    fn cursor_and_scroll() {
+        let mut input = InputState::new();
+        assert_eq!(input.cursor(), None);
+
+        input.update(&cursor_moved(3.0, 4.0));
+        assert_eq!(input.cursor(), Some(PhysicalPosition::new(3.0, 4.0)));
+        input.update(&WindowEvent::CursorLeft {
+            device_id: device(),
+        });
+        assert_eq!(input.cursor(), None);
+
+        #[allow(deprecated)]
+        let scroll = |delta| WindowEvent::MouseWheel {
+            device_id: device(),
+            delta,
+            phase: winit::event::TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        };
+        input.update(&scroll(MouseScrollDelta::LineDelta(1.0, -2.0)));
+        input.update(&scroll(MouseScrollDelta::PixelDelta(
+            PhysicalPosition::new(PIXELS_PER_LINE, PIXELS_PER_LINE * 2.0),
+        )));
+        assert_eq!(input.scroll_delta(), (2.0, 0.0));
+        input.end_frame();
+        assert_eq!(input.scroll_delta(), (0.0, 0.0));
+
+        assert!(!input.update(&WindowEvent::Focused(true)));
+    }
+
+    #[test]
+    fn canvas_cursor_skips_the_letterbox() {
+        let config = RendererConfig {
+            scaling_mode: ScalingMode::AspectFit,
+            ..RendererConfig::default()
+        };
+        // A 4x3 canvas in a 10x20 window has bars above and below
+        let renderer = Renderer::software_with_config(4, 3, 10, 20, config).unwrap();
+        let mut input = InputState::new();
+        assert_eq!(input.canvas_cursor(&renderer), None);
+
+        input.update(&cursor_moved(5.0, 2.0));
+        assert_eq!(input.canvas_cursor(&renderer), None);
+
+        input.update(&cursor_moved(0.0, 6.25));
+        assert_eq!(input.canvas_cursor(&renderer), Some((0, 0)));
+        input.update(&cursor_moved(9.99, 13.74));
+        assert_eq!(input.canvas_cursor(&renderer), Some((3, 2)));
+    }
+}
@@ -0,0 +1,288 @@
+// Layer API, the demo only draws on the base layer
+#![allow(dead_code)]
+
+use crate::blend::BlendMode;
+use crate::render::{Pixel, PixelBuffer};
+
+// -----------------------------------------------------------------------------
+//     - Layer id -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LayerId(u32);
+
+// -----------------------------------------------------------------------------
+//     - Canvas layer -
+//     The cpu side of a layer. The renderer keeps a texture for each of
+//     these and composites them, lowest `z` first, every frame.
+// -----------------------------------------------------------------------------
+pub struct CanvasLayer {
+    id: LayerId,
+    z: i32,
+    pub pixels: PixelBuffer,
+    /// 0.0 is fully transparent, 1.0 is as drawn
+    pub opacity: f32,
+    /// How the layer is composited onto the layers below it
+    pub blend_mode: BlendMode,
+    pub visible: bool,
+    /// Offset from the top left corner of the canvas, in canvas pixels
+    pub x: i32,
+    pub y: i32,
+}
+
+impl CanvasLayer {
+    pub fn id(&self) -> LayerId {
+        self.id
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Layer stack -
+//     Always holds at least the base layer, which decides the canvas size.
+// -----------------------------------------------------------------------------
+pub struct LayerStack {
+    /// Sorted by z, layers with the same z keep the order they were added in
+    /// (ids only go up, so that's the id order)
+    layers: Vec<CanvasLayer>,
+    next_id: u32,
+}
+
+impl LayerStack {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut stack = Self {
+            layers: Vec::new(),
+            next_id: 0,
+        };
+        stack.add(width, height);
+        // The base is opaque black, like the new space of a resized canvas
+        let base = &mut stack.layers[0];
+        base.pixels.fill(Pixel::black());
+        base.blend_mode = BlendMode::Replace;
+        stack
+    }
+
+    pub fn base_id(&self) -> LayerId {
+        LayerId(0)
+    }
+
+    pub fn base(&self) -> &CanvasLayer {
+        self.get(self.base_id()).expect("the base layer is never removed")
+    }
+
+    pub fn base_mut(&mut self) -> &mut CanvasLayer {
+        let id = self.base_id();
+        self.get_mut(id).expect("the base layer is never removed")
+    }
+
+    /// Canvas size, which is the size of the base layer
+    pub fn size(&self) -> (u32, u32) {
+        let pixels = &self.base().pixels;
+        (pixels.width(), pixels.height())
+    }
+
+    /// Add a transparent layer on top of all the others, unlike the base
+    /// layer which starts out opaque black
+    pub fn add(&mut self, width: u32, height: u32) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+
+        let mut pixels = PixelBuffer::new(width, height);
+        pixels.fill(Default::default());
+
+        let z = self
+            .layers
+            .last()
+            .map(|l| l.z.saturating_add(1))
+            .unwrap_or(0);
+        self.layers.push(CanvasLayer {
+            id,
+            z,
+            pixels,
+            opacity: 1.0,
+            blend_mode: BlendMode::SourceOver,
+            visible: true,
+            x: 0,
+            y: 0,
+        });
+
+        id
+    }
+
+    /// Remove a layer. The base layer can't be removed.
+    pub fn remove(&mut self, id: LayerId) -> Option<CanvasLayer> {
+        if id == self.base_id() {
+            return None;
+        }
+        let index = self.layers.iter().position(|l| l.id == id)?;
+        Some(self.layers.remove(index))
+    }
+
+    pub fn get(&self, id: LayerId) -> Option<&CanvasLayer> {
+        self.layers.iter().find(|l| l.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: LayerId) -> Option<&mut CanvasLayer> {
+        self.layers.iter_mut().find(|l| l.id == id)
+    }
+
+    /// Move a layer in the stack. Higher `z` is drawn on top.
+    pub fn set_z(&mut self, id: LayerId, z: i32) {
+        if let Some(layer) = self.get_mut(id) {
+            layer.z = z;
+            self.layers.sort_by_key(|l| (l.z, l.id));
+        }
+    }
+
+    /// Put a layer above all the others. Once `z` reaches `i32::MAX` it
+    /// ties with the layers there, which are drawn in the order they were
+    /// added in.
+    pub fn raise_to_top(&mut self, id: LayerId) {
+        let others = self.layers.iter().filter(|l| l.id != id);
+        if let Some(top) = others.map(|l| l.z).max() {
+            self.set_z(id, top.saturating_add(1));
+        }
+    }
+
+    /// Put a layer below all the others, ties at `i32::MIN` are drawn in the
+    /// order they were added in
+    pub fn lower_to_bottom(&mut self, id: LayerId) {
+        let others = self.layers.iter().filter(|l| l.id != id);
+        if let Some(bottom) = others.map(|l| l.z).min() {
+            self.set_z(id, bottom.saturating_sub(1));
+        }
+    }
+
+    /// Bottom to top
+    pub fn iter(&self) -> impl Iterator<Item = &CanvasLayer> {
+        self.layers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut CanvasLayer> {
+        self.layers.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::Rect;
+    use crate::render::test_util::rgba;
+    use crate::render::Renderer;
+
+    fn order(stack: &LayerStack) -> Vec<LayerId> {
+        stack.iter().map(|l| l.id()).collect()
+    }
+
+    #[test]
+    fn new_canvas_is_opaque_black() {
+        let mut renderer = Renderer::new_software(3, 2, 3, 2).unwrap();
+        renderer.draw().unwrap();
+        let canvas = renderer.read_canvas().unwrap();
+        for (x, y, p) in canvas.enumerate() {
+            assert_eq!(rgba(p), (0, 0, 0, 255), "at ({}, {})", x, y);
+        }
+
+        // Only the layers on top start out transparent
+        let id = renderer.layers_mut().add(2, 2);
+        let layer = renderer.layers().get(id).unwrap();
+        assert!(layer.pixels.enumerate().all(|(_, _, p)| p.a == 0));
+    }
+
+    #[test]
+    fn layers_are_removed() {
+        let mut stack = LayerStack::new(4, 4);
+        let base = stack.base_id();
+        let a = stack.add(4, 4);
+        let b = stack.add(2, 2);
+
+        let removed = stack.remove(a).unwrap();
+        assert_eq!(removed.id(), a);
+        assert!(stack.get(a).is_none());
+        assert!(stack.remove(a).is_none());
+        assert_eq!(order(&stack), vec![base, b]);
+
+        // The base layer stays, and so does the canvas size
+        assert!(stack.remove(base).is_none());
+        assert_eq!(order(&stack), vec![base, b]);
+        assert_eq!(stack.size(), (4, 4));
+    }
+
+    #[test]
+    fn layers_are_reordered() {
+        let mut stack = LayerStack::new(4, 4);
+        let base = stack.base_id();
+        let a = stack.add(4, 4);
+        let b = stack.add(4, 4);
+        assert_eq!(order(&stack), vec![base, a, b]);
+
+        stack.raise_to_top(base);
+        assert_eq!(order(&stack), vec![a, b, base]);
+
+        stack.lower_to_bottom(b);
+        assert_eq!(order(&stack), vec![b, a, base]);
+
+        stack.set_z(a, 100);
+        assert_eq!(stack.get(a).unwrap().z(), 100);
+        assert_eq!(order(&stack), vec![b, base, a]);
+    }
+
+    #[test]
+    fn ties_keep_the_order_layers_were_added_in() {
+        let mut stack = LayerStack::new(4, 4);
+        let base = stack.base_id();
+        let a = stack.add(4, 4);
+        let b = stack.add(4, 4);
+
+        // b goes to 5 first, a joining it later still goes below it
+        stack.set_z(b, 5);
+        stack.set_z(a, 5);
+        stack.set_z(base, 5);
+        assert_eq!(order(&stack), vec![base, a, b]);
+    }
+
+    #[test]
+    fn z_saturates() {
+        let mut stack = LayerStack::new(4, 4);
+        let base = stack.base_id();
+        let a = stack.add(4, 4);
+
+        stack.set_z(a, i32::MAX);
+        let b = stack.add(4, 4);
+        assert_eq!(stack.get(b).unwrap().z(), i32::MAX);
+        assert_eq!(order(&stack), vec![base, a, b]);
+
+        stack.raise_to_top(base);
+        assert_eq!(stack.get(base).unwrap().z(), i32::MAX);
+
+        stack.set_z(a, i32::MIN);
+        stack.lower_to_bottom(b);
+        assert_eq!(stack.get(b).unwrap().z(), i32::MIN);
+        assert_eq!(order(&stack), vec![a, b, base]);
+    }
+
+    #[test]
+    fn only_changed_layers_are_uploaded() {
+        let mut renderer = Renderer::new_software(16, 16, 16, 16).unwrap();
+        let a = renderer.layers_mut().add(16, 16);
+        let b = renderer.layers_mut().add(8, 8);
+        renderer.draw().unwrap();
+
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 0);
+
+        let layer = renderer.layers_mut().get_mut(b).unwrap();
+        let rect = Rect::new(2, 3, 4, 2);
+        let red = Pixel::new(255, 0, 0, 255);
+        layer.pixels.fill_rect(rect, red, BlendMode::Replace);
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 4 * 2 * 4);
+
+        let layer = renderer.layers_mut().get_mut(a).unwrap();
+        layer.pixels.set(15, 15, Pixel::new(0, 255, 0, 255));
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 4);
+    }
+}
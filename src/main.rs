@@ -1,495 +1,170 @@
-use futures::executor::block_on;
-use wgpu::util::DeviceExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use winit::{
-    dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::WindowBuilder,
 };
 
+mod adapter;
+mod blend;
+mod blit;
+mod compute;
+mod config;
+mod draw;
+mod error;
+mod filter;
+mod format;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod input;
+mod layer;
+mod mipmap;
+mod pacing;
+mod post;
+mod readback;
+mod record;
 mod render;
-
-#[derive(Debug, Copy, Clone)]
-struct Pixel {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8,
-}
-
-impl Pixel {
-    fn bytes(&self) -> Vec<u8> {
-        vec![self.r, self.g, self.b, self.a]
-    }
-
-    fn red() -> Self {
-        Pixel {
-            r: 255,
-            g: 0,
-            b: 0,
-            a: 128,
-        }
-    }
-
-    fn blue() -> Self {
-        Pixel {
-            r: 0,
-            g: 0,
-            b: 255,
-            a: 128,
-        }
-    }
-}
-
-struct Layer {
-    texture: wgpu::Texture,
-    width: u32,
-    height: u32,
-    texture_size: wgpu::Extent3d,
-}
-
-fn red() -> Vec<Pixel> {
-    vec![Pixel::red(); 262144 / 4]
-}
-
-fn blue() -> Vec<Pixel> {
-    vec![Pixel::blue(); 262144 / 4]
-}
-
-const VERTICES: &[Vertex] = &[
-    // Top left 0
-    Vertex {
-        position: [-1.0, 1.0, 0.0],
-        tex_coords: [0.0, 0.0],
-    },
-    // Top right 1
-    Vertex {
-        position: [1.0, 1.0, 0.0],
-        tex_coords: [1.0, 0.0],
-    },
-    // Bottom left 2
-    Vertex {
-        position: [-1.0, -1.0, 0.0],
-        tex_coords: [0.0, 1.0],
-    },
-    // Bottom right 3
-    Vertex {
-        position: [1.0, -1.0, 0.0],
-        tex_coords: [1.0, 1.0],
-    },
-];
-
-const INDICES: &[u16] = &[0, 2, 3, 0, 3, 1];
+mod scaling;
+mod shadertoy;
+mod software;
+mod surface;
+mod text;
 
 // -----------------------------------------------------------------------------
-//     - Vertex-
+//     - Scene -
+//     What gets drawn every frame: a red dot that H moves left, and a soft
+//     brush under the cursor while the left button is held.
 // -----------------------------------------------------------------------------
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
+struct Scene {
+    x: u32,
+    y: u32,
+    brush: render::Pixel,
 }
 
-impl Vertex {
-    fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
-        wgpu::VertexBufferDescriptor {
-            stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttributeDescriptor {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float3,
-                },
-                wgpu::VertexAttributeDescriptor {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float2,
-                },
-            ],
-        }
-    }
-}
-
-unsafe impl bytemuck::Pod for Vertex {}
-unsafe impl bytemuck::Zeroable for Vertex {}
-
-// -----------------------------------------------------------------------------
-//     - State-
-//     Maybe absolute nonsense:
-//     Device -> [ Queue -> SwapChain -> RenderPipeline -> Surface ]
-// -----------------------------------------------------------------------------
-struct State {
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
-    size: PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    diffuse_bind_group: wgpu::BindGroup,
-    layers: Vec<Layer>,
-}
-
-impl State {
-    async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
-        let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    shader_validation: true,
-                },
-                None,
-            )
-            .await
-            .unwrap();
-
-        let sc_desc = wgpu::SwapChainDescriptor {
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
-
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-
-        // -----------------------------------------------------------------------------
-        //     - Texture -
-        // -----------------------------------------------------------------------------
-        let diffuse_bytes = include_bytes!("../textures/supertexture.png");
-        let diffuse_image = image::load_from_memory(diffuse_bytes).unwrap();
-        let diffuse_rgba = diffuse_image.as_rgba8().unwrap();
-        // let diffuse_rgba = red().into_iter().map(|p| p.bytes().into_iter()).flatten().collect::<Vec<_>>();
-
-        use image::GenericImageView;
-        let (width, height) = diffuse_image.dimensions();
-        let texture_size = wgpu::Extent3d {
-            width,
-            height,
-            depth: 1,
-        };
-
-        let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: Some("omg textures!!!!"),
-        });
-
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            diffuse_rgba,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * width,
-                rows_per_image: height,
-            },
-            texture_size,
-        );
-
-        let diffuse_texture_view =
-            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        // let layer_one_texture_view =
-        //     layer_one_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let layer_one = Layer {
-            width,
-            height,
-            texture: diffuse_texture,
-            texture_size: wgpu::Extent3d {
-                width: width,
-                height: height,
-                depth: 1,
-            },
-        };
-
-        let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::SampledTexture {
-                            multisampled: false,
-                            dimension: wgpu::TextureViewDimension::D2,
-                            component_type: wgpu::TextureComponentType::Uint,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler { comparison: false },
-                        count: None,
-                    },
-                ],
-                label: Some("texture binding group layout"),
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                },
-            ],
-            label: Some("meh"),
-        });
-
-        // -----------------------------------------------------------------------------
-        //     - Shader bits -
-        // -----------------------------------------------------------------------------
-        let vs_module = device.create_shader_module(wgpu::include_spirv!("shader.vert.spv"));
-        let fs_module = device.create_shader_module(wgpu::include_spirv!("shader.frag.spv"));
-
-        // buffer business
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex buffer yaaaay"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index buffer because things aren't hard enough as they are"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsage::INDEX,
-        });
-
-        // -----------------------------------------------------------------------------
-        //     - Pipeline -
-        // -----------------------------------------------------------------------------
-        let render_pipeline = create_pipeline(
-            &device,
-            &sc_desc,
-            vs_module,
-            fs_module,
-            texture_bind_group_layout,
-        );
+impl Scene {
+    fn new(renderer: &mut render::Renderer) -> Self {
+        // The canvas is kept premultiplied, so colours drawn into it are too
+        let pixels = renderer.pixels();
+        pixels.set_alpha_mode(blend::AlphaMode::Premultiplied);
+        let (x, y) = (pixels.width() / 2, pixels.height() / 2);
 
         Self {
-            surface,
-            device,
-            queue,
-            sc_desc,
-            swap_chain,
-            size,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices: INDICES.len() as u32,
-            diffuse_bind_group,
-            layers: vec![layer_one],
+            x,
+            y,
+            // Painting over the same spot builds it up to white
+            brush: blend::premultiply(render::Pixel::new(255, 255, 255, 64)),
         }
     }
 
-    fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        self.size = new_size;
-        self.sc_desc.width = new_size.width;
-        self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+    fn move_left(&mut self) {
+        self.x = self.x.saturating_sub(1);
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        false
-    }
-
-    fn update(&mut self) {
-        let mut diffuse_rgba = blue()
-            .into_iter()
-            .map(|p| p.bytes())
-            .flatten()
-            .collect::<Vec<_>>();
-        let index = diffuse_rgba.len() / 2;
-        diffuse_rgba
-            .iter_mut()
-            .skip(index)
-            .map(|p| *p = 255)
-            .collect::<Vec<_>>();
-
-        let layer = &self.layers[0];
-
-        self.queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &layer.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            diffuse_rgba.as_slice(),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * layer.width,
-                rows_per_image: layer.height,
-            },
-            layer.texture_size,
-        );
-    }
-
-    fn render(&mut self) {
-        let frame = self
-            .swap_chain
-            .get_current_frame()
-            .expect("Timeout?")
-            .output;
+    /// Draw into the canvas and upload what changed
+    fn draw(
+        &mut self,
+        renderer: &mut render::Renderer,
+        input: &input::InputState,
+    ) -> Result<(), error::RendererError> {
+        let pixels = renderer.pixels();
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("I haz label"),
-            });
+        if let Some(old) = pixels.get_mut(self.x + 1, self.y) {
+            old.r = 0;
+        }
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
+        if let Some(new) = pixels.get_mut(self.x, self.y) {
+            new.r = 255;
+        }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..));
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        let cursor = input.canvas_cursor(renderer);
+        let pressed = input.button_held(MouseButton::Left);
+        if pressed {
+            if let Some((cx, cy)) = cursor {
+                let (cx, cy) = (cx as i32, cy as i32);
+                renderer
+                    .pixels()
+                    .plot(cx, cy, self.brush, blend::BlendMode::SourceOver);
+            }
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+        renderer.update_shadertoy_mouse(cursor, pressed);
+        renderer.draw()
     }
 }
 
-fn create_pipeline(
-    device: &wgpu::Device,
-    sc_desc: &wgpu::SwapChainDescriptor,
-    vs_module: wgpu::ShaderModule,
-    fs_module: wgpu::ShaderModule,
-    texture_bind_group: wgpu::BindGroupLayout,
-) -> wgpu::RenderPipeline {
-    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Render pipeline layout what does this even mean"),
-        bind_group_layouts: &[&texture_bind_group],
-        push_constant_ranges: &[],
-    });
-
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Pipeline omg pipeline (render okay)"),
-        layout: Some(&render_pipeline_layout),
-        vertex_stage: wgpu::ProgrammableStageDescriptor {
-            module: &vs_module,
-            entry_point: "main",
-        },
-        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-            module: &fs_module,
-            entry_point: "main",
-        }),
-        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: wgpu::CullMode::Back,
-            depth_bias: 0,
-            depth_bias_slope_scale: 0.0,
-            depth_bias_clamp: 0.0,
-            clamp_depth: false,
-        }),
-        color_states: &[wgpu::ColorStateDescriptor {
-            format: sc_desc.format,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
-            write_mask: wgpu::ColorWrite::ALL,
-        }],
-        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-        depth_stencil_state: None,
-        vertex_state: wgpu::VertexStateDescriptor {
-            index_format: wgpu::IndexFormat::Uint16,
-            vertex_buffers: &[Vertex::desc()],
-        },
-        sample_count: 1,
-        sample_mask: !0,
-        alpha_to_coverage_enabled: false,
-    });
-
-    render_pipeline
-}
-
 // -----------------------------------------------------------------------------
 //     - Main-
 // -----------------------------------------------------------------------------
 fn main() {
+    let mut config = config::RendererConfig::from_env();
+    if config::list_adapters_from_env() {
+        let adapters = adapter::list_adapters(config.backends);
+        if adapters.is_empty() {
+            eprintln!("no adapters found");
+        }
+        for info in adapters {
+            println!("{} ({:?}, {:?})", info.name, info.backend, info.device_type);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    // let mut state = block_on(State::new(&window));
-
-    let mut renderer = render::Renderer::new(128, 128, &window);
+    config.hot_reload = cfg!(feature = "hot-reload");
+    // F10 saves them
+    config.record_history = 10;
+    let mut renderer = match render::Renderer::with_config(128, 128, &window, config) {
+        Ok(renderer) => renderer,
+        Err(e) => {
+            eprintln!("failed to create the renderer: {}", e);
+            return;
+        }
+    };
+    let mut input = input::InputState::new();
+    let mut scene = Scene::new(&mut renderer);
 
-    let mut x = 128 / 2;
-    let mut y = 64 * 128;
+    // T toggles it
+    let mut shadertoy = false;
+    // L toggles the game of life, C swaps its alive and dead colours
+    let mut life = None;
+    let mut life_colors: [f32; 8] = [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0];
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::RedrawRequested(_) => {
-                let pixels = renderer.pixels();
-
-                let old = pixels.flap(x + 1 + y);
-                old.r = 0;
-
-                let new = pixels.flap(x + y);
-                new.r = 255;
-
-                renderer.draw();
+                if let Err(e) = scene.draw(&mut renderer, &input) {
+                    eprintln!("failed to upload the canvas: {}", e);
+                    *control_flow = ControlFlow::Exit;
+                }
+                if let Some(e) = renderer.take_recording_error() {
+                    eprintln!("recording stopped: {}", e);
+                }
             }
             Event::MainEventsCleared => {
-                window.request_redraw();
-                renderer.render();
+                #[cfg(feature = "hot-reload")]
+                for error in renderer.reload_shaders() {
+                    eprintln!("{}", error);
+                }
+
+                if renderer.frame_due() {
+                    window.request_redraw();
+                    if let Err(e) = renderer.render() {
+                        eprintln!("failed to render: {}", e);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    input.end_frame();
+                }
+
+                // Don't undo an exit
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = renderer.control_flow();
+                }
             }
             Event::WindowEvent { ref event, .. } => {
+                input.update(event);
+
                 match event {
                     WindowEvent::Resized(physical_size) => {
                         renderer.resize(*physical_size);
@@ -512,8 +187,122 @@ fn main() {
                                 virtual_keycode: Some(VirtualKeyCode::H),
                                 ..
                             } => {
-                                x -= 1;
-                                // left
+                                scene.move_left();
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            } => {
+                                let path = format!("screenshot-{}.png", timestamp());
+                                match renderer.capture_screenshot(&path) {
+                                    Ok(()) => eprintln!("saved {}", path),
+                                    Err(e) => eprintln!("failed to save {}: {}", path, e),
+                                }
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::R),
+                                ..
+                            } => {
+                                let result = match renderer.is_recording() {
+                                    true => renderer.stop_recording(),
+                                    false => {
+                                        let path = format!("recording-{}.y4m", timestamp());
+                                        eprintln!("recording to {}", path);
+                                        let output = record::RecordOutput::Y4m(path.into());
+                                        renderer.start_recording(output)
+                                    }
+                                };
+                                if let Err(e) = result {
+                                    eprintln!("recording failed: {}", e);
+                                }
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F10),
+                                ..
+                            } => {
+                                let path = format!("replay-{}.y4m", timestamp());
+                                let output = record::RecordOutput::Y4m(path.clone().into());
+                                match renderer.save_recent(output) {
+                                    Ok(()) => eprintln!("saved {}", path),
+                                    Err(e) => eprintln!("failed to save {}: {}", path, e),
+                                }
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::T),
+                                ..
+                            } => {
+                                shadertoy = !shadertoy;
+                                let shader: Option<&[u8]> = match shadertoy {
+                                    true => Some(include_bytes!("shadertoy/trails.frag.spv")),
+                                    false => None,
+                                };
+                                if let Err(e) = renderer.set_shadertoy(shader) {
+                                    eprintln!("failed to set the shader: {}", e);
+                                }
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::P),
+                                ..
+                            } => {
+                                let effects: &[post::PostEffect] =
+                                    match renderer.post_effects().is_empty() {
+                                        true => &[
+                                            post::PostEffect::Bloom,
+                                            post::PostEffect::ChromaticAberration,
+                                            post::PostEffect::Scanlines,
+                                            post::PostEffect::Crt,
+                                            post::PostEffect::Vignette,
+                                        ],
+                                        false => &[],
+                                    };
+                                if let Err(e) = renderer.set_post_effects(effects) {
+                                    eprintln!("failed to set the post effects: {}", e);
+                                }
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::L),
+                                ..
+                            } => {
+                                life = match life.take() {
+                                    Some(id) => {
+                                        renderer.remove_compute(id);
+                                        None
+                                    }
+                                    None => {
+                                        let shader = compute::ComputeShader {
+                                            spirv: include_bytes!("compute/life.comp.spv"),
+                                            workgroups: compute::Workgroups::PerPixel(8, 8),
+                                            uniforms: bytemuck::cast_slice(&life_colors).to_vec(),
+                                            buffers: Vec::new(),
+                                        };
+                                        match renderer.add_compute(&shader) {
+                                            Ok(id) => Some(id),
+                                            Err(e) => {
+                                                eprintln!("failed to add life: {}", e);
+                                                None
+                                            }
+                                        }
+                                    }
+                                };
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::C),
+                                ..
+                            } => {
+                                life_colors.rotate_left(4);
+                                if let Some(id) = life {
+                                    let uniforms = bytemuck::cast_slice(&life_colors);
+                                    if let Err(e) = renderer.set_compute_uniforms(id, uniforms) {
+                                        eprintln!("failed to set the colours: {}", e);
+                                    }
+                                }
                             }
                             _ => {}
                         }
@@ -525,3 +314,75 @@ fn main() {
         }
     });
 }
+
+/// Milliseconds since the epoch, to name screenshots and recordings
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::test_util::{button, cursor_moved, rgba_at};
+
+    #[test]
+    fn app_loop_draws_the_scene() {
+        // Every canvas pixel is 2x2 in the window
+        let mut renderer = render::Renderer::new_software(4, 4, 8, 8).unwrap();
+        let mut input = input::InputState::new();
+        let mut scene = Scene::new(&mut renderer);
+
+        let frames: Vec<Vec<WindowEvent>> = vec![
+            vec![cursor_moved(1.0, 1.0)],
+            vec![button(MouseButton::Left, ElementState::Pressed)],
+            vec![],
+            vec![button(MouseButton::Left, ElementState::Released)],
+            vec![cursor_moved(7.0, 1.0)],
+        ];
+        let mut frames = frames.into_iter().map(|events| {
+            for event in &events {
+                input.update(event);
+            }
+            scene.draw(&mut renderer, &input).unwrap();
+            renderer.render().unwrap();
+            input.end_frame();
+            renderer.read_frame().unwrap()
+        });
+
+        let frame = frames.next().unwrap();
+        assert_eq!(rgba_at(&frame, 4, 4), (255, 0, 0, 255));
+        assert_eq!(rgba_at(&frame, 5, 5), (255, 0, 0, 255));
+        assert_eq!(rgba_at(&frame, 0, 0), (0, 0, 0, 255));
+
+        // The brush builds up while the button is held
+        let frame = frames.next().unwrap();
+        assert_eq!(rgba_at(&frame, 0, 0), (64, 64, 64, 255));
+        let frame = frames.next().unwrap();
+        assert_eq!(rgba_at(&frame, 1, 1), (112, 112, 112, 255));
+        for frame in frames {
+            assert_eq!(rgba_at(&frame, 0, 0), (112, 112, 112, 255));
+            assert_eq!(rgba_at(&frame, 6, 0), (0, 0, 0, 255));
+        }
+    }
+
+    #[test]
+    fn app_loop_moves_the_dot() {
+        let mut renderer = render::Renderer::new_software(4, 4, 4, 4).unwrap();
+        let input = input::InputState::new();
+        let mut scene = Scene::new(&mut renderer);
+
+        for _ in 0..3 {
+            scene.draw(&mut renderer, &input).unwrap();
+            renderer.render().unwrap();
+            scene.move_left();
+        }
+
+        let frame = renderer.read_frame().unwrap();
+        let row = (0..4).map(|x| rgba_at(&frame, x, 2).0).collect::<Vec<_>>();
+        // Drawn at 2, then 1 and 0, each one clearing the one before
+        assert_eq!(row, [255, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The level above the one being rendered
+layout(set = 0, binding = 0) uniform texture2D t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+void main() {
+    // Halfway between four texels, so the linear sampler averages them
+    f_color = texture(sampler2D(t_source, s_source), v_tex_coords);
+}
@@ -0,0 +1,175 @@
+use std::num::NonZeroU32;
+
+// -----------------------------------------------------------------------------
+//     - Mipmap generator -
+//     Fills in the mip levels of a texture after level 0 was uploaded, by
+//     drawing each level into the next one at half the size with a linear
+//     sampler. Sampling an sRGB texture gives linear values, so the average
+//     is taken in linear space.
+// -----------------------------------------------------------------------------
+pub struct MipmapGenerator {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl MipmapGenerator {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        component_type: wgpu::TextureComponentType,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("mipmap bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("mipmap sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let vs_module = device.create_shader_module(wgpu::include_spirv!("mipmap.vert.spv"));
+        let fs_module = device.create_shader_module(wgpu::include_spirv!("mipmap.frag.spv"));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("mipmap pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("mipmap pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor::default()),
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            layout,
+            sampler,
+            pipeline,
+        }
+    }
+
+    /// The views and bind groups to render every level after the first of
+    /// `texture`. The texture needs `OUTPUT_ATTACHMENT` usage.
+    pub fn chain(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        mip_level_count: u32,
+    ) -> MipChain {
+        let level_view = |level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip level"),
+                base_mip_level: level,
+                level_count: NonZeroU32::new(1),
+                ..Default::default()
+            })
+        };
+
+        let levels = (1..mip_level_count)
+            .map(|level| {
+                let source = level_view(level - 1);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("mip level bind group"),
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                });
+                (bind_group, level_view(level))
+            })
+            .collect();
+
+        MipChain { levels }
+    }
+
+    /// Record the passes that regenerate the chain from level 0
+    pub fn generate(&self, encoder: &mut wgpu::CommandEncoder, chain: &MipChain) {
+        for (bind_group, target) in &chain.levels {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Mip chain -
+// -----------------------------------------------------------------------------
+pub struct MipChain {
+    /// Reads the level above, renders to this one
+    levels: Vec<(wgpu::BindGroup, wgpu::TextureView)>,
+}
+
+impl MipChain {
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
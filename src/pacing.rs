@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use winit::event_loop::ControlFlow;
+
+/// Frames the timing stats are taken over
+const FRAME_HISTORY: usize = 120;
+
+// -----------------------------------------------------------------------------
+//     - Frame stats -
+//     Over the last `FRAME_HISTORY` frames, except `dropped` which is the
+//     total.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    /// Frames that were due but didn't happen because the previous one was
+    /// late. Only counted with a target frame rate.
+    pub dropped: u64,
+}
+
+// -----------------------------------------------------------------------------
+//     - Frame pacer -
+//     Decides when the next frame is due and keeps track of how long
+//     frames take. Without a target frame rate every frame is due right
+//     away.
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct FramePacer {
+    interval: Option<Duration>,
+    next_frame: Instant,
+    last_frame: Option<Instant>,
+    frame_times: VecDeque<Duration>,
+    dropped: u64,
+}
+
+impl FramePacer {
+    pub fn new(target_fps: Option<u32>) -> Self {
+        Self {
+            interval: target_fps
+                .filter(|&fps| fps > 0)
+                .map(|fps| Duration::from_secs(1) / fps),
+            next_frame: Instant::now(),
+            last_frame: None,
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            dropped: 0,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.interval.is_none() || now >= self.next_frame
+    }
+
+    /// What the event loop should do until the next frame. Keeps polling
+    /// without a target frame rate.
+    pub fn control_flow(&self) -> ControlFlow {
+        match self.interval {
+            Some(_) => ControlFlow::WaitUntil(self.next_frame),
+            None => ControlFlow::Poll,
+        }
+    }
+
+    /// Call once a frame has been presented
+    pub fn frame_presented(&mut self, now: Instant) {
+        let last = self.last_frame.replace(now);
+        if let Some(last) = last {
+            if self.frame_times.len() == FRAME_HISTORY {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(now - last);
+        }
+
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        // However long it took to get to the first frame doesn't count
+        if last.is_none() {
+            self.next_frame = now + interval;
+            return;
+        }
+
+        // Stay on the same schedule, unless the next frame is already due,
+        // then every frame time that has passed is dropped and the
+        // schedule starts over from now
+        self.next_frame += interval;
+        if now >= self.next_frame {
+            let late = now - self.next_frame;
+            self.dropped += (late.as_nanos() / interval.as_nanos()) as u64 + 1;
+            self.next_frame = now + interval;
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let count = self.frame_times.len() as u32;
+        if count == 0 {
+            return FrameStats {
+                dropped: self.dropped,
+                ..Default::default()
+            };
+        }
+
+        let total: Duration = self.frame_times.iter().sum();
+        FrameStats {
+            min: *self
+                .frame_times
+                .iter()
+                .min()
+                .unwrap_or(&Duration::default()),
+            avg: total / count,
+            max: *self
+                .frame_times
+                .iter()
+                .max()
+                .unwrap_or(&Duration::default()),
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn uncapped_is_always_due() {
+        for &fps in &[None, Some(0)] {
+            let mut pacer = FramePacer::new(fps);
+            let start = Instant::now();
+            assert!(pacer.is_due(start));
+            assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+
+            pacer.frame_presented(start);
+            assert!(pacer.is_due(start));
+            assert_eq!(pacer.control_flow(), ControlFlow::Poll);
+        }
+    }
+
+    #[test]
+    fn capped_waits_for_the_next_frame() {
+        let mut pacer = FramePacer::new(Some(10));
+        let start = Instant::now();
+        assert!(pacer.is_due(start));
+
+        pacer.frame_presented(start);
+        assert!(!pacer.is_due(start + ms(99)));
+        assert!(pacer.is_due(start + ms(100)));
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(start + ms(100))
+        );
+
+        // A frame that's a little late doesn't move the schedule
+        pacer.frame_presented(start + ms(130));
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(start + ms(200))
+        );
+        assert_eq!(pacer.stats().dropped, 0);
+    }
+
+    #[test]
+    fn late_frames_are_dropped() {
+        let mut pacer = FramePacer::new(Some(10));
+        let start = Instant::now();
+        pacer.frame_presented(start);
+        pacer.frame_presented(start + ms(100));
+
+        // Due at 200, presented at 350: 300 was missed
+        pacer.frame_presented(start + ms(350));
+        assert_eq!(pacer.stats().dropped, 1);
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(start + ms(450))
+        );
+
+        // Due at 450, on time
+        pacer.frame_presented(start + ms(450));
+        assert_eq!(pacer.stats().dropped, 1);
+
+        // Due at 550, presented at 1000: 650 through 950 were missed
+        pacer.frame_presented(start + ms(1000));
+        assert_eq!(pacer.stats().dropped, 5);
+        assert_eq!(
+            pacer.control_flow(),
+            ControlFlow::WaitUntil(start + ms(1100))
+        );
+    }
+
+    #[test]
+    fn uncapped_never_drops() {
+        let mut pacer = FramePacer::new(None);
+        let start = Instant::now();
+        pacer.frame_presented(start);
+        pacer.frame_presented(start + ms(5000));
+        assert_eq!(pacer.stats().dropped, 0);
+    }
+
+    #[test]
+    fn stats_are_over_the_frame_times() {
+        let mut pacer = FramePacer::new(None);
+        assert_eq!(pacer.stats(), FrameStats::default());
+
+        // The first frame has nothing to be timed against
+        let start = Instant::now();
+        pacer.frame_presented(start);
+        assert_eq!(pacer.stats(), FrameStats::default());
+
+        for &t in &[100, 350, 450, 1000] {
+            pacer.frame_presented(start + ms(t));
+        }
+        let stats = pacer.stats();
+        assert_eq!(
+            (stats.min, stats.avg, stats.max),
+            (ms(100), ms(250), ms(550))
+        );
+    }
+
+    #[test]
+    fn stats_forget_old_frames() {
+        let mut pacer = FramePacer::new(None);
+        let start = Instant::now();
+        pacer.frame_presented(start);
+        pacer.frame_presented(start + ms(1000));
+
+        for i in 1..=FRAME_HISTORY as u64 {
+            pacer.frame_presented(start + ms(1000 + i * 10));
+        }
+        let stats = pacer.stats();
+        assert_eq!((stats.min, stats.avg, stats.max), (ms(10), ms(10), ms(10)));
+    }
+}
@@ -0,0 +1,428 @@
+#[cfg(feature = "hot-reload")]
+use std::borrow::Cow;
+use std::time::Instant;
+
+use winit::dpi::PhysicalSize;
+
+use crate::error::RendererError;
+
+/// First word of every SPIR-V module
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Check that `bytes` look like a SPIR-V module, which wgpu would otherwise
+/// panic on. Only the length and the magic number are checked, the rest is
+/// up to the driver.
+pub fn check_spirv(bytes: &[u8]) -> Result<(), RendererError> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(4) {
+        return Err(RendererError::InvalidSpirv("length is not a multiple of 4"));
+    }
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if magic != SPIRV_MAGIC {
+        return Err(RendererError::InvalidSpirv("wrong magic number"));
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - Post effect -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostEffect {
+    /// Barrel distortion, like the glass of an old tv
+    Crt,
+    /// Darkens every other row of window pixels
+    Scanlines,
+    /// Darkens the corners
+    Vignette,
+    /// Bright parts glow
+    Bloom,
+    /// Red and blue drift apart towards the edges
+    ChromaticAberration,
+    /// A fragment shader compiled to SPIR-V, e.g.
+    /// `include_bytes!("post/my_effect.frag.spv").to_vec()` or one loaded
+    /// at runtime. Anything in `src/post`
+    /// is compiled by `build.rs`, see `post/vignette.frag` for the inputs
+    /// it gets.
+    // The demo only uses the built in effects
+    #[allow(dead_code)]
+    Custom(Vec<u8>),
+}
+
+impl PostEffect {
+    fn spirv(&self) -> &[u8] {
+        match self {
+            PostEffect::Crt => include_bytes!("post/crt.frag.spv"),
+            PostEffect::Scanlines => include_bytes!("post/scanlines.frag.spv"),
+            PostEffect::Vignette => include_bytes!("post/vignette.frag.spv"),
+            PostEffect::Bloom => include_bytes!("post/bloom.frag.spv"),
+            PostEffect::ChromaticAberration => {
+                include_bytes!("post/chromatic_aberration.frag.spv")
+            }
+            PostEffect::Custom(spirv) => spirv,
+        }
+    }
+
+    /// The GLSL source, for hot reloading. `None` for custom effects, only
+    /// the built in ones are reloaded.
+    #[cfg(feature = "hot-reload")]
+    pub fn source_path(&self) -> Option<&'static str> {
+        let path = match self {
+            PostEffect::Crt => concat!(env!("CARGO_MANIFEST_DIR"), "/src/post/crt.frag"),
+            PostEffect::Scanlines => {
+                concat!(env!("CARGO_MANIFEST_DIR"), "/src/post/scanlines.frag")
+            }
+            PostEffect::Vignette => concat!(env!("CARGO_MANIFEST_DIR"), "/src/post/vignette.frag"),
+            PostEffect::Bloom => concat!(env!("CARGO_MANIFEST_DIR"), "/src/post/bloom.frag"),
+            PostEffect::ChromaticAberration => concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/post/chromatic_aberration.frag"
+            ),
+            PostEffect::Custom(_) => return None,
+        };
+        Some(path)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Post uniforms -
+//     The same for every pass in a frame
+// -----------------------------------------------------------------------------
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PostUniforms {
+    resolution: [f32; 2],
+    time: f32,
+    frame: u32,
+}
+
+unsafe impl bytemuck::Pod for PostUniforms {}
+unsafe impl bytemuck::Zeroable for PostUniforms {}
+
+// -----------------------------------------------------------------------------
+//     - Post chain -
+//     Full screen passes between the layers and the frame. The layers are
+//     drawn into one of two window sized textures, then every pass reads
+//     one and writes the other, except for the last one which writes the
+//     frame.
+// -----------------------------------------------------------------------------
+pub struct PostChain {
+    effects: Vec<PostEffect>,
+    pipelines: Vec<wgpu::RenderPipeline>,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    uniforms: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    /// Ping pong textures, `None` while there are no effects or the window
+    /// has no size
+    targets: Option<[PostTarget; 2]>,
+    size: PhysicalSize<u32>,
+    started: Instant,
+    frame: u32,
+}
+
+struct PostTarget {
+    view: wgpu::TextureView,
+    /// Reads this target
+    bind_group: wgpu::BindGroup,
+}
+
+impl PostChain {
+    /// `format` is what the layers and the passes render to, i.e. the
+    /// swap chain format
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module =
+            device.create_shader_module(wgpu::include_spirv!("post/fullscreen.vert.spv"));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("post uniforms"),
+            size: std::mem::size_of::<PostUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            effects: Vec::new(),
+            pipelines: Vec::new(),
+            layout,
+            pipeline_layout,
+            vs_module,
+            sampler,
+            uniforms,
+            format,
+            targets: None,
+            size: PhysicalSize::new(0, 0),
+            started: Instant::now(),
+            frame: 0,
+        }
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    /// Replace the passes, they run in order. Nothing changes if one of
+    /// them isn't SPIR-V.
+    pub fn set_effects(
+        &mut self,
+        device: &wgpu::Device,
+        effects: &[PostEffect],
+    ) -> Result<(), RendererError> {
+        for effect in effects {
+            check_spirv(effect.spirv())?;
+        }
+
+        self.pipelines = effects
+            .iter()
+            .map(|effect| {
+                let fs_module = device.create_shader_module(wgpu::util::make_spirv(effect.spirv()));
+                self.create_pipeline(device, &fs_module)
+            })
+            .collect();
+        self.effects = effects.to_vec();
+        self.create_targets(device);
+        Ok(())
+    }
+
+    /// Swap the fragment shader of one pass, e.g. after it was recompiled
+    #[cfg(feature = "hot-reload")]
+    pub fn set_shader(&mut self, device: &wgpu::Device, index: usize, spirv: Vec<u32>) {
+        let source = wgpu::ShaderModuleSource::SpirV(Cow::Owned(spirv));
+        let fs_module = device.create_shader_module(source);
+        let pipeline = self.create_pipeline(device, &fs_module);
+        if let Some(old) = self.pipelines.get_mut(index) {
+            *old = pipeline;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.size = size;
+        self.create_targets(device);
+    }
+
+    /// Where the layers should be drawn, `None` if they go straight to the
+    /// frame
+    pub fn input(&self) -> Option<&wgpu::TextureView> {
+        self.targets.as_ref().map(|[first, _]| &first.view)
+    }
+
+    /// Record the passes for the next frame, the last one renders to
+    /// `output`
+    pub fn run(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+    ) {
+        if self.targets.is_none() {
+            return;
+        }
+
+        let uniforms = PostUniforms {
+            resolution: [self.size.width as f32, self.size.height as f32],
+            time: self.started.elapsed().as_secs_f32(),
+            frame: self.frame,
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+        self.frame = self.frame.wrapping_add(1);
+
+        self.rerun(encoder, output);
+    }
+
+    /// Record the passes again with the time and frame of the last `run`
+    pub fn rerun(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let targets = match &self.targets {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        let last = self.pipelines.len() - 1;
+        for (i, pipeline) in self.pipelines.iter().enumerate() {
+            let (source, target) = (&targets[i % 2], &targets[(i + 1) % 2]);
+            let attachment = match i == last {
+                true => output,
+                false => &target.view,
+            };
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &source.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn create_targets(&mut self, device: &wgpu::Device) {
+        let (width, height) = (self.size.width, self.size.height);
+        if self.is_empty() || width == 0 || height == 0 {
+            self.targets = None;
+            return;
+        }
+
+        let target = || {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("post target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post bind group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer(self.uniforms.slice(..)),
+                    },
+                ],
+            });
+
+            PostTarget { view, bind_group }
+        };
+
+        self.targets = Some([target(), target()]);
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        fs_module: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("post pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &self.vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor::default()),
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: self.format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_spirv, PostEffect};
+
+    #[test]
+    fn builtin_effects_are_spirv() {
+        let effects = [
+            PostEffect::Crt,
+            PostEffect::Scanlines,
+            PostEffect::Vignette,
+            PostEffect::Bloom,
+            PostEffect::ChromaticAberration,
+        ];
+        for effect in effects.iter() {
+            assert!(check_spirv(effect.spirv()).is_ok(), "{:?}", effect);
+        }
+    }
+
+    #[test]
+    fn bad_spirv_is_rejected() {
+        assert!(check_spirv(&[]).is_err());
+        assert!(check_spirv(&[0x03, 0x02, 0x23, 0x07, 0x00]).is_err());
+        assert!(check_spirv(&[0x07, 0x23, 0x02, 0x03]).is_err());
+        assert!(check_spirv(b"void main() {}\n").is_err());
+        assert!(check_spirv(&[0x03, 0x02, 0x23, 0x07]).is_ok());
+    }
+}
@@ -0,0 +1,43 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The output of the previous pass, or the canvas for the first one
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2) uniform PostUniforms {
+    // Of the window, in pixels
+    vec2 u_resolution;
+    // Seconds since the renderer was created
+    float u_time;
+    uint u_frame;
+};
+
+const float THRESHOLD = 0.7;
+const float INTENSITY = 0.6;
+const int RADIUS = 4;
+// Window pixels between samples
+const float SPREAD = 2.0;
+
+// Blur whatever is brighter than the threshold and add it on top. One pass,
+// so it's only cheap enough for small radiuses.
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_input), v_tex_coords);
+    vec2 texel = SPREAD / u_resolution;
+
+    vec3 glow = vec3(0.0);
+    float total = 0.0;
+    for (int y = -RADIUS; y <= RADIUS; y++) {
+        for (int x = -RADIUS; x <= RADIUS; x++) {
+            vec2 offset = vec2(float(x), float(y));
+            float weight = exp(-dot(offset, offset) / float(RADIUS * RADIUS));
+            vec3 s = texture(sampler2D(t_input, s_input), v_tex_coords + offset * texel).rgb;
+            glow += max(s - THRESHOLD, vec3(0.0)) * weight;
+            total += weight;
+        }
+    }
+
+    f_color = vec4(color.rgb + glow / total * INTENSITY, color.a);
+}
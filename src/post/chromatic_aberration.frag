@@ -0,0 +1,30 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The output of the previous pass, or the canvas for the first one
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2) uniform PostUniforms {
+    // Of the window, in pixels
+    vec2 u_resolution;
+    // Seconds since the renderer was created
+    float u_time;
+    uint u_frame;
+};
+
+// Window pixels red and blue are pulled apart at the edges
+const float AMOUNT = 3.0;
+
+// Split the colour channels like a cheap lens, more towards the edges
+void main() {
+    vec2 from_center = v_tex_coords - 0.5;
+    vec2 offset = from_center * 2.0 * AMOUNT / u_resolution;
+
+    vec4 color = texture(sampler2D(t_input, s_input), v_tex_coords);
+    color.r = texture(sampler2D(t_input, s_input), v_tex_coords + offset).r;
+    color.b = texture(sampler2D(t_input, s_input), v_tex_coords - offset).b;
+    f_color = color;
+}
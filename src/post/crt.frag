@@ -0,0 +1,32 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The output of the previous pass, or the canvas for the first one
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2) uniform PostUniforms {
+    // Of the window, in pixels
+    vec2 u_resolution;
+    // Seconds since the renderer was created
+    float u_time;
+    uint u_frame;
+};
+
+const float CURVATURE = 0.08;
+
+// Bend the picture like the glass of an old tv
+void main() {
+    vec2 centered = v_tex_coords * 2.0 - 1.0;
+    centered *= 1.0 + CURVATURE * dot(centered, centered);
+    vec2 tex_coords = centered * 0.5 + 0.5;
+
+    if (any(lessThan(tex_coords, vec2(0.0))) || any(greaterThan(tex_coords, vec2(1.0)))) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    f_color = texture(sampler2D(t_input, s_input), tex_coords);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The output of the previous pass, or the canvas for the first one
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2) uniform PostUniforms {
+    // Of the window, in pixels
+    vec2 u_resolution;
+    // Seconds since the renderer was created
+    float u_time;
+    uint u_frame;
+};
+
+const float PI = 3.14159265;
+const float STRENGTH = 0.35;
+
+// Darken every other row of window pixels
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_input), v_tex_coords);
+    // Pixel centres are at .5, so this is 1 and -1 on alternating rows
+    float row = sin(gl_FragCoord.y * PI);
+    color.rgb *= 1.0 - STRENGTH * (0.5 + 0.5 * row);
+    f_color = color;
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+// The output of the previous pass, or the canvas for the first one
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2) uniform PostUniforms {
+    // Of the window, in pixels
+    vec2 u_resolution;
+    // Seconds since the renderer was created
+    float u_time;
+    uint u_frame;
+};
+
+const float INNER = 0.35;
+const float OUTER = 0.85;
+
+// Darken towards the corners
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_input), v_tex_coords);
+    vec2 from_center = v_tex_coords - 0.5;
+    // Keep it round on wide windows
+    from_center.x *= u_resolution.x / max(u_resolution.y, 1.0);
+    color.rgb *= 1.0 - smoothstep(INNER, OUTER, length(from_center));
+    f_color = color;
+}
@@ -0,0 +1,104 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::error::RendererError;
+use crate::render::{Pixel, PixelBuffer};
+
+/// Pixels on their way back from the gpu, see `PresentBackend::read_canvas`
+pub type CanvasReadback = Pin<Box<dyn Future<Output = Result<PixelBuffer, RendererError>>>>;
+
+// -----------------------------------------------------------------------------
+//     - Readback -
+//     A texture copied into a buffer the cpu can map. Rows in the buffer
+//     have to start on a 256 byte boundary, so they are padded on the way
+//     in and the padding is skipped on the way out.
+// -----------------------------------------------------------------------------
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    size: (u32, u32),
+    bytes_per_pixel: u32,
+    padded_row: u32,
+}
+
+impl Readback {
+    /// Record a copy of level 0 of `texture`, it's done once `encoder` is
+    /// submitted. The texture needs `COPY_SRC` usage.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        (width, height): (u32, u32),
+        bytes_per_pixel: u32,
+    ) -> Self {
+        let unpadded_row = bytes_per_pixel * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_row,
+                    rows_per_image: height,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+
+        Self {
+            buffer,
+            size: (width, height),
+            bytes_per_pixel,
+            padded_row,
+        }
+    }
+
+    /// Map the buffer and turn the bytes of every texel into a `Pixel`.
+    /// Call after submitting the copy. The future only resolves once the
+    /// device is polled, which every submit does as well.
+    pub fn pixels(
+        self,
+        pixel: impl Fn(&[u8]) -> Pixel + 'static,
+    ) -> impl Future<Output = Result<PixelBuffer, RendererError>> {
+        // Requested now rather than on the first poll of the future, so
+        // polling the device before awaiting it is enough
+        let mapping = self.buffer.slice(..).map_async(wgpu::MapMode::Read);
+
+        async move {
+            mapping.await?;
+
+            let (width, height) = self.size;
+            let mut pixels = PixelBuffer::new(width, height);
+            {
+                let data = self.buffer.slice(..).get_mapped_range();
+                let rows = data.chunks_exact(self.padded_row as usize);
+                for (dst, src) in pixels.rows_mut().zip(rows) {
+                    let texels = src.chunks_exact(self.bytes_per_pixel as usize);
+                    for (p, bytes) in dst.iter_mut().zip(texels) {
+                        *p = pixel(bytes);
+                    }
+                }
+            }
+            self.buffer.unmap();
+
+            Ok(pixels)
+        }
+    }
+}
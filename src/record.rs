@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::error::RendererError;
+use crate::render::PixelBuffer;
+
+// -----------------------------------------------------------------------------
+//     - Record output -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+// The demo only records y4m files
+#[allow(dead_code)]
+pub enum RecordOutput {
+    /// A YUV4MPEG2 file, which ffmpeg and mpv read as it is. Alpha is
+    /// dropped.
+    Y4m(PathBuf),
+    /// `frame-00000.png`, `frame-00001.png` and so on in this directory,
+    /// which is created if it doesn't exist
+    PngSequence(PathBuf),
+    /// Raw rgba frames on stdout, e.g. for
+    /// `ffmpeg -f rawvideo -pix_fmt rgba -s 128x128 -r 60 -i - out.mp4`.
+    /// Anything else printed to stdout ends up in the video, so messages
+    /// should go to stderr.
+    RawStdout,
+}
+
+// -----------------------------------------------------------------------------
+//     - Frame recorder -
+//     Every frame passed to `capture` is written out while recording, and
+//     the last few are kept in memory so they can be saved after the fact.
+//     Frames aren't timed, each one is 1 / fps seconds of video.
+// -----------------------------------------------------------------------------
+pub struct FrameRecorder {
+    fps: u32,
+    sink: Option<Sink>,
+    /// The last `history_len` frames, oldest first
+    history: VecDeque<PixelBuffer>,
+    history_len: usize,
+}
+
+impl FrameRecorder {
+    /// Keeps the last `history_secs` seconds of frames around for
+    /// `save_history`, 0 keeps none
+    pub fn new(fps: u32, history_secs: u32) -> Self {
+        let fps = fps.max(1);
+        Self {
+            fps,
+            sink: None,
+            history: VecDeque::new(),
+            history_len: fps.saturating_mul(history_secs) as usize,
+        }
+    }
+
+    // The demo doesn't need it back, it set it
+    #[allow(dead_code)]
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Start writing frames to `output`, stopping any recording that was
+    /// already going
+    pub fn start(&mut self, output: RecordOutput) -> Result<(), RendererError> {
+        self.stop()?;
+        self.sink = Some(Sink::open(output, self.fps)?);
+        Ok(())
+    }
+
+    /// Finish the recording, if there is one
+    pub fn stop(&mut self) -> Result<(), RendererError> {
+        match self.sink.take() {
+            Some(sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the frames in memory to `output`, the recording (if any) goes
+    /// on as it was
+    pub fn save_history(&self, output: RecordOutput) -> Result<(), RendererError> {
+        let mut sink = Sink::open(output, self.fps)?;
+        for frame in &self.history {
+            sink.write(frame)?;
+        }
+        sink.finish()
+    }
+
+    /// Add a frame. A failed write stops the recording, the frame is still
+    /// kept in memory.
+    pub fn capture(&mut self, frame: &PixelBuffer) -> Result<(), RendererError> {
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(frame.clone());
+        }
+
+        let sink = match &mut self.sink {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        if let Err(e) = sink.write(frame) {
+            self.sink = None;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Sink -
+//     Where a recording goes. Streams take their size from the first frame
+//     and can't change it after.
+// -----------------------------------------------------------------------------
+enum Sink {
+    Y4m {
+        out: BufWriter<File>,
+        fps: u32,
+        size: Option<(u32, u32)>,
+    },
+    Png {
+        dir: PathBuf,
+        next: u32,
+    },
+    Raw {
+        out: BufWriter<io::Stdout>,
+        size: Option<(u32, u32)>,
+    },
+}
+
+impl Sink {
+    fn open(output: RecordOutput, fps: u32) -> Result<Self, RendererError> {
+        let sink = match output {
+            RecordOutput::Y4m(path) => Sink::Y4m {
+                out: BufWriter::new(File::create(path)?),
+                fps,
+                size: None,
+            },
+            RecordOutput::PngSequence(dir) => {
+                fs::create_dir_all(&dir)?;
+                Sink::Png { dir, next: 0 }
+            }
+            RecordOutput::RawStdout => Sink::Raw {
+                out: BufWriter::new(io::stdout()),
+                size: None,
+            },
+        };
+
+        Ok(sink)
+    }
+
+    fn write(&mut self, frame: &PixelBuffer) -> Result<(), RendererError> {
+        match self {
+            Sink::Y4m { out, fps, size } => {
+                if size.is_none() {
+                    writeln!(
+                        out,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg",
+                        frame.width(),
+                        frame.height(),
+                        fps
+                    )?;
+                }
+                check_size(size, frame)?;
+                out.write_all(b"FRAME\n")?;
+                out.write_all(&yuv420(frame))?;
+            }
+            Sink::Png { dir, next } => {
+                frame.save(dir.join(format!("frame-{:05}.png", next)))?;
+                *next += 1;
+            }
+            Sink::Raw { out, size } => {
+                check_size(size, frame)?;
+                out.write_all(frame)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), RendererError> {
+        match self {
+            Sink::Y4m { mut out, .. } => out.flush()?,
+            Sink::Raw { mut out, .. } => out.flush()?,
+            Sink::Png { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Remember the size of the first frame, and fail on frames that don't
+/// match it
+fn check_size(size: &mut Option<(u32, u32)>, frame: &PixelBuffer) -> Result<(), RendererError> {
+    let actual = (frame.width(), frame.height());
+    match *size.get_or_insert(actual) {
+        expected if expected == actual => Ok(()),
+        expected => Err(RendererError::RecordSizeMismatch { expected, actual }),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Yuv 4:2:0 -
+//     BT.601 limited range, what players assume when the stream doesn't
+//     say. The Y plane is full size, U and V are half size (rounded up)
+//     with each value the average of a 2x2 block.
+// -----------------------------------------------------------------------------
+fn yuv420(frame: &PixelBuffer) -> Vec<u8> {
+    let (width, height) = (frame.width(), frame.height());
+    let (chroma_w, chroma_h) = (width.div_ceil(2), height.div_ceil(2));
+
+    let mut yuv = Vec::with_capacity((width * height + 2 * chroma_w * chroma_h) as usize);
+    // u, v and the number of pixels summed for every 2x2 block
+    let mut blocks = vec![[0.0; 3]; (chroma_w * chroma_h) as usize];
+
+    for (x, y, p) in frame.enumerate() {
+        let (r, g, b) = (p.r as f64, p.g as f64, p.b as f64);
+        yuv.push((16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8);
+
+        let block = &mut blocks[((y / 2) * chroma_w + x / 2) as usize];
+        block[0] += (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0;
+        block[1] += (112.439 * r - 94.154 * g - 18.285 * b) / 256.0;
+        block[2] += 1.0;
+    }
+
+    for c in 0..2 {
+        let plane = blocks
+            .iter()
+            .map(|block| (128.0 + block[c] / block[2]).round() as u8);
+        yuv.extend(plane);
+    }
+
+    yuv
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{yuv420, FrameRecorder, RecordOutput};
+    use crate::error::RendererError;
+    use crate::render::test_util::TempPath;
+    use crate::render::{Pixel, PixelBuffer};
+
+    fn frame(width: u32, height: u32, value: u8) -> PixelBuffer {
+        let mut frame = PixelBuffer::new(width, height);
+        frame.fill(Pixel::new(value, value, value, 255));
+        frame
+    }
+
+    #[test]
+    fn y4m_has_a_header_and_420_planes() {
+        let path = TempPath::new("planes.y4m");
+        let mut recorder = FrameRecorder::new(30, 0);
+        recorder.start(RecordOutput::Y4m(path.0.clone())).unwrap();
+        recorder.capture(&frame(3, 3, 0)).unwrap();
+        recorder.capture(&frame(3, 3, 255)).unwrap();
+        recorder.stop().unwrap();
+
+        let bytes = fs::read(&path.0).unwrap();
+        let header = b"YUV4MPEG2 W3 H3 F30:1 Ip A1:1 C420jpeg\n";
+        assert_eq!(&bytes[..header.len()], &header[..]);
+
+        // 3x3 luma and two 2x2 chroma planes for each frame
+        let frames = &bytes[header.len()..];
+        let frame_len = b"FRAME\n".len() + 9 + 2 * 4;
+        assert_eq!(frames.len(), 2 * frame_len);
+        for (frame, luma) in frames.chunks_exact(frame_len).zip([16, 235].iter()) {
+            assert_eq!(&frame[..6], b"FRAME\n");
+            assert!(frame[6..15].iter().all(|y| y == luma));
+            assert!(frame[15..].iter().all(|&c| c == 128));
+        }
+    }
+
+    #[test]
+    fn chroma_is_averaged_over_2x2() {
+        let mut frame = frame(2, 1, 0);
+        *frame.get_mut(0, 0).unwrap() = Pixel::new(255, 0, 0, 255);
+        let yuv = yuv420(&frame);
+        assert_eq!(yuv.len(), 2 + 2);
+        // BT.601 red and black
+        assert_eq!(yuv[..2], [81, 16]);
+        // The block is half red: u = 128 - 38 / 2, v = 128 + 112 / 2
+        assert_eq!(yuv[2..], [109, 184]);
+    }
+
+    #[test]
+    fn frames_must_keep_their_size() {
+        let path = TempPath::new("size.y4m");
+        let mut recorder = FrameRecorder::new(30, 0);
+        recorder.start(RecordOutput::Y4m(path.0.clone())).unwrap();
+        recorder.capture(&frame(4, 4, 0)).unwrap();
+
+        match recorder.capture(&frame(4, 2, 0)) {
+            Err(RendererError::RecordSizeMismatch { expected, actual }) => {
+                assert_eq!((expected, actual), ((4, 4), (4, 2)));
+            }
+            other => panic!("expected a size mismatch, got {:?}", other),
+        }
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn history_keeps_the_newest_frames() {
+        // 2 fps for 2 seconds
+        let mut recorder = FrameRecorder::new(2, 2);
+        for value in 0..6 {
+            recorder.capture(&frame(1, 1, value)).unwrap();
+        }
+
+        let kept = recorder
+            .history
+            .iter()
+            .map(|frame| frame.get(0, 0).unwrap().r)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [2, 3, 4, 5]);
+
+        let mut recorder = FrameRecorder::new(60, 0);
+        recorder.capture(&frame(1, 1, 0)).unwrap();
+        assert!(recorder.history.is_empty());
+    }
+
+    #[test]
+    fn history_length_saturates() {
+        let recorder = FrameRecorder::new(u32::MAX, u32::MAX);
+        assert_eq!(recorder.history_len, u32::MAX as usize);
+    }
+}
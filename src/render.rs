@@ -1,14 +1,42 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::time::Instant;
 
 use futures::executor::block_on;
+use futures::future;
 use wgpu::util::DeviceExt;
 use winit::{
-    dpi::PhysicalSize,
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::ControlFlow,
+    window::Window,
 };
 
+use crate::adapter::select_adapter;
+use crate::blend::{blend, srgb_to_linear, AlphaMode, BlendMode};
+use crate::compute::{ComputeId, ComputePasses, ComputeShader};
+use crate::config::RendererConfig;
+use crate::draw::Rect;
+use crate::error::RendererError;
+use crate::filter::FilterMode;
+use crate::format::CanvasFormat;
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::{catch_panic, ShaderError, ShaderWatcher};
+use crate::layer::{CanvasLayer, LayerId, LayerStack};
+use crate::mipmap::{MipChain, MipmapGenerator};
+use crate::pacing::{FramePacer, FrameStats};
+use crate::post::{PostChain, PostEffect};
+use crate::readback::{CanvasReadback, Readback};
+use crate::record::{FrameRecorder, RecordOutput};
+use crate::scaling::{ScalingMode, Viewport};
+use crate::shadertoy::{Shadertoy, ShadertoyMouse};
+use crate::software::SoftwareBackend;
+use crate::surface::{clamp_size, SurfaceState, SurfaceStats};
+
+#[cfg(test)]
+pub mod test_util;
+
 // -----------------------------------------------------------------------------
 //     - Vertex-
 // -----------------------------------------------------------------------------
@@ -72,20 +100,237 @@ const VERTICES: &[Vertex] = &[
 
 const INDICES: &[u16] = &[0, 2, 3, 0, 3, 1];
 
+/// Dirty regions a `PixelBuffer` keeps track of before merging them
+const MAX_DIRTY_RECTS: usize = 8;
+
+/// Largest texture every adapter has to support. wgpu 0.6 doesn't report
+/// the actual limit.
+pub const MAX_TEXTURE_SIZE: u32 = 8192;
+
+pub const MAX_CANVAS_SIZE: u32 = MAX_TEXTURE_SIZE;
+
+/// Layers and the canvas have to be at least 1x1 and fit in a texture
+pub fn check_canvas_size(width: u32, height: u32) -> Result<(), RendererError> {
+    let valid = |n| n > 0 && n <= MAX_CANVAS_SIZE;
+    match valid(width) && valid(height) {
+        true => Ok(()),
+        false => Err(RendererError::InvalidCanvasSize { width, height }),
+    }
+}
+
+/// Headless output has to be at least 1x1 and fit in a texture
+pub fn check_output_size(width: u32, height: u32) -> Result<(), RendererError> {
+    let valid = |n| n > 0 && n <= MAX_TEXTURE_SIZE;
+    match valid(width) && valid(height) {
+        true => Ok(()),
+        false => Err(RendererError::InvalidOutputSize { width, height }),
+    }
+}
+
+/// Pixels uploaded to a texture have to be the texture's size, and the
+/// part uploaded has to be inside them
+fn check_upload(
+    texture: (u32, u32),
+    pixels: &PixelBuffer,
+    rect: Rect,
+) -> Result<(), RendererError> {
+    let size = (pixels.width(), pixels.height());
+    if size != texture {
+        return Err(RendererError::UploadSizeMismatch {
+            expected: texture,
+            actual: size,
+        });
+    }
+
+    match pixels.bounds().contains_rect(&rect) {
+        true => Ok(()),
+        false => Err(RendererError::UploadOutOfBounds { rect, size }),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Layer uniforms -
+// -----------------------------------------------------------------------------
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LayerUniforms {
+    /// Left, top, right, bottom in clip space
+    rect: [f32; 4],
+    opacity: f32,
+    /// 1.0 if the texture holds premultiplied alpha
+    premultiplied: f32,
+    texture_size: [f32; 2],
+    /// 1.0 to sample with `FilterMode::SharpBilinear`
+    sharp_bilinear: f32,
+    _padding: [f32; 3],
+}
+
+unsafe impl bytemuck::Pod for LayerUniforms {}
+unsafe impl bytemuck::Zeroable for LayerUniforms {}
+
+impl LayerUniforms {
+    fn new(
+        layer: &CanvasLayer,
+        canvas_size: (u32, u32),
+        viewport: Viewport,
+        window_size: (u32, u32),
+        filter: FilterMode,
+    ) -> Self {
+        // Window pixels to clip space
+        let clip_x = |x: f32| x / window_size.0.max(1) as f32 * 2.0 - 1.0;
+        let clip_y = |y: f32| 1.0 - y / window_size.1.max(1) as f32 * 2.0;
+        let [left, top, right, bottom] = viewport.layer_rect(layer, canvas_size);
+
+        Self {
+            rect: [clip_x(left), clip_y(top), clip_x(right), clip_y(bottom)],
+            opacity: layer.opacity.clamp(0.0, 1.0),
+            premultiplied: match layer.pixels.alpha_mode() {
+                AlphaMode::Straight => 0.0,
+                AlphaMode::Premultiplied => 1.0,
+            },
+            texture_size: [layer.pixels.width() as f32, layer.pixels.height() as f32],
+            sharp_bilinear: match filter {
+                FilterMode::SharpBilinear => 1.0,
+                _ => 0.0,
+            },
+            _padding: [0.0; 3],
+        }
+    }
+}
+
 // -----------------------------------------------------------------------------
 //     - Layer -
+//     The gpu side of a `CanvasLayer`
 // -----------------------------------------------------------------------------
 struct Layer {
+    id: LayerId,
+    format: CanvasFormat,
     texture: wgpu::Texture,
     width: u32,
     height: u32,
-    texture_size: wgpu::Extent3d,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Empty unless the filter uses mipmaps
+    mips: MipChain,
+}
+
+impl Layer {
+    /// A texture with the format, filter and sampler of `state`
+    fn new(state: &State, id: LayerId, width: u32, height: u32) -> Self {
+        let device = &state.device;
+        let format = state.canvas_format;
+        let texture_size = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+
+        // The mip levels are rendered to, and compute shaders copy the
+        // texture back and forth
+        let mip_level_count = state.filter.mip_level_count(width, height);
+        let mut usage = wgpu::TextureUsage::SAMPLED
+            | wgpu::TextureUsage::COPY_DST
+            | wgpu::TextureUsage::COPY_SRC;
+        if mip_level_count > 1 {
+            usage |= wgpu::TextureUsage::OUTPUT_ATTACHMENT;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: format.texture_format(),
+            usage,
+            label: Some("omg textures!!!!"),
+        });
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("layer uniforms"),
+            size: std::mem::size_of::<LayerUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = create_bind_group(
+            device,
+            &state.texture_bind_group_layout,
+            &texture,
+            &state.diffuse_sampler,
+            &uniforms,
+        );
+        let mips = state.mipmaps.chain(device, &texture, mip_level_count);
+
+        Self {
+            id,
+            format,
+            texture,
+            width,
+            height,
+            uniforms,
+            bind_group,
+            mips,
+        }
+    }
+
+    /// Upload part of the pixels, returns the number of bytes uploaded.
+    fn upload(
+        &self,
+        queue: &wgpu::Queue,
+        pixels: &PixelBuffer,
+        rect: Rect,
+    ) -> Result<u64, RendererError> {
+        check_upload((self.width, self.height), pixels, rect)?;
+
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let (data, layout) = match self.format.encode(pixels, rect) {
+            Some(bytes) => {
+                let layout = wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: bytes_per_pixel * rect.width,
+                    rows_per_image: rect.height,
+                };
+                (Cow::Owned(bytes), layout)
+            }
+            None => {
+                // Point at the first pixel of the rect and let the row
+                // stride skip over the rest of each row.
+                let layout = wgpu::TextureDataLayout {
+                    offset: (rect.y as u64 * self.width as u64 + rect.x as u64) * 4,
+                    bytes_per_row: 4 * self.width,
+                    rows_per_image: rect.height,
+                };
+                (Cow::Borrowed(&pixels[..]), layout)
+            }
+        };
+
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: rect.x as u32,
+                    y: rect.y as u32,
+                    z: 0,
+                },
+            },
+            &data,
+            layout,
+            wgpu::Extent3d {
+                width: rect.width,
+                height: rect.height,
+                depth: 1,
+            },
+        );
+
+        Ok(rect.area() * bytes_per_pixel as u64)
+    }
 }
 
 // -----------------------------------------------------------------------------
 //     - Pixel -
 // -----------------------------------------------------------------------------
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 #[repr(C)]
 pub struct Pixel {
     pub r: u8,
@@ -95,7 +340,11 @@ pub struct Pixel {
 }
 
 impl Pixel {
-    fn black() -> Self {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub fn black() -> Self {
         Self {
             r: 0,
             g: 0,
@@ -110,21 +359,209 @@ unsafe impl bytemuck::Zeroable for Pixel {}
 
 // -----------------------------------------------------------------------------
 //     - Pixel buffer -
+//     Row major, top left is (0, 0)
 // -----------------------------------------------------------------------------
+#[derive(Clone)]
 pub struct PixelBuffer {
     inner: Vec<Pixel>,
+    width: u32,
+    height: u32,
+    alpha_mode: AlphaMode,
+    /// Regions changed since the last `take_dirty`, see `mark_dirty`
+    dirty: Vec<Rect>,
 }
 
+// The canvas API, the demo only needs a few of these
+#[allow(dead_code)]
 impl PixelBuffer {
-    pub fn with_capacity(cap: usize) -> Self {
-        Self {
-            inner: (0..cap).map(|_| Pixel::black()).collect(),
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut buffer = Self {
+            inner: vec![Pixel::black(); width as usize * height as usize],
+            width,
+            height,
+            alpha_mode: AlphaMode::default(),
+            dirty: Vec::new(),
+        };
+        buffer.mark_all_dirty();
+        buffer
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Whether the pixels in this buffer (and the colours drawn into it)
+    /// are straight or premultiplied alpha.
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    pub fn set_alpha_mode(&mut self, mode: AlphaMode) {
+        self.alpha_mode = mode;
+    }
+
+    /// True if the pixels may have changed since the last `take_dirty`.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+
+    /// The changed regions, marking the buffer clean.
+    pub fn take_dirty(&mut self) -> Vec<Rect> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Record a changed region. Touching regions are merged, and when
+    /// there are too many the two that waste the least area when merged
+    /// are combined, so there are never more than `MAX_DIRTY_RECTS`.
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let mut rect = match rect.intersect(&Rect::new(0, 0, self.width, self.height)) {
+            Some(rect) => rect,
+            None => return,
+        };
+
+        if self.dirty.iter().any(|r| r.contains_rect(&rect)) {
+            return;
+        }
+
+        // Growing a rect can make it touch others, so keep going until
+        // nothing changes
+        while let Some(i) = self.dirty.iter().position(|r| r.touches(&rect)) {
+            rect = rect.union(&self.dirty.swap_remove(i));
+        }
+        self.dirty.push(rect);
+
+        while self.dirty.len() > MAX_DIRTY_RECTS {
+            let mut best = (0, 1, u64::MAX);
+            for i in 0..self.dirty.len() {
+                for j in i + 1..self.dirty.len() {
+                    let (a, b) = (&self.dirty[i], &self.dirty[j]);
+                    let waste = a.union(b).area() - a.area() - b.area();
+                    if waste < best.2 {
+                        best = (i, j, waste);
+                    }
+                }
+            }
+
+            let b = self.dirty.swap_remove(best.1);
+            let a = self.dirty.swap_remove(best.0);
+            self.mark_dirty(a.union(&b));
         }
     }
 
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+    }
+
     pub fn flap(&mut self, index: usize) -> &mut Pixel {
+        let width = self.width.max(1) as usize;
+        self.mark_dirty(Rect::new(
+            (index % width) as i32,
+            (index / width) as i32,
+            1,
+            1,
+        ));
         &mut self.inner[index]
     }
+
+    /// Flat index of (x, y), or `None` if the coordinate is outside the buffer.
+    pub fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<&Pixel> {
+        let index = self.index(x, y)?;
+        self.inner.get(index)
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut Pixel> {
+        let index = self.index(x, y)?;
+        self.mark_dirty(Rect::new(x as i32, y as i32, 1, 1));
+        self.inner.get_mut(index)
+    }
+
+    /// Write a pixel, anything outside the buffer is silently clipped.
+    pub fn set(&mut self, x: u32, y: u32, pixel: Pixel) {
+        if let Some(p) = self.get_mut(x, y) {
+            *p = pixel;
+        }
+    }
+
+    /// Blend a pixel onto the buffer, anything outside the buffer is
+    /// silently clipped.
+    pub fn blend_pixel(&mut self, x: u32, y: u32, pixel: Pixel, mode: BlendMode) {
+        let alpha = self.alpha_mode;
+        if let Some(p) = self.get_mut(x, y) {
+            *p = blend(pixel, *p, mode, alpha);
+        }
+    }
+
+    /// Copy of the buffer at a new size. The top left corner is anchored,
+    /// new space is black and anything past the new edges is dropped.
+    pub fn resized(&self, width: u32, height: u32) -> Self {
+        let mut resized = Self::new(width, height);
+        resized.alpha_mode = self.alpha_mode;
+        let keep = width.min(self.width) as usize;
+        for (src, dst) in self.rows().zip(resized.rows_mut()) {
+            dst[..keep].copy_from_slice(&src[..keep]);
+        }
+        resized
+    }
+
+    pub fn fill(&mut self, pixel: Pixel) {
+        self.mark_all_dirty();
+        self.inner.iter_mut().for_each(|p| *p = pixel);
+    }
+
+    pub fn row(&self, y: u32) -> Option<&[Pixel]> {
+        let start = self.index(0, y)?;
+        Some(&self.inner[start..start + self.width as usize])
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [Pixel]> {
+        let start = self.index(0, y)?;
+        self.mark_dirty(Rect::new(0, y as i32, self.width, 1));
+        Some(&mut self.inner[start..start + self.width as usize])
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Pixel]> {
+        self.inner.chunks_exact(self.width.max(1) as usize)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [Pixel]> {
+        self.mark_all_dirty();
+        self.inner.chunks_exact_mut(self.width.max(1) as usize)
+    }
+
+    /// Every pixel together with its coordinate, row by row.
+    pub fn enumerate(&self) -> impl Iterator<Item = (u32, u32, &Pixel)> {
+        let width = self.width.max(1);
+        self.inner
+            .iter()
+            .enumerate()
+            .map(move |(i, p)| (i as u32 % width, i as u32 / width, p))
+    }
+
+    pub fn enumerate_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut Pixel)> {
+        self.mark_all_dirty();
+        let width = self.width.max(1);
+        self.inner
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, p)| (i as u32 % width, i as u32 / width, p))
+    }
 }
 
 impl Deref for PixelBuffer {
@@ -137,192 +574,603 @@ impl Deref for PixelBuffer {
 
 impl DerefMut for PixelBuffer {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.mark_all_dirty();
         bytemuck::cast_slice_mut(&mut self.inner)
     }
 }
 
+// -----------------------------------------------------------------------------
+//     - Present backend -
+//     Whatever turns the layer stack into a frame. `State` does it with
+//     wgpu, `SoftwareBackend` does the same on the cpu.
+// -----------------------------------------------------------------------------
+pub trait PresentBackend {
+    /// Pick up new, removed, resized and dirty layers
+    fn sync_layers(&mut self, stack: &mut LayerStack) -> Result<(), RendererError>;
+
+    /// Composite the layers, as of the last `sync_layers`, into a frame
+    fn render(
+        &mut self,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+    ) -> Result<(), RendererError>;
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>);
+
+    /// Size of the frames, i.e. the window
+    fn size(&self) -> PhysicalSize<u32>;
+
+    /// The last rendered frame, if the backend can read it back
+    fn read_frame(&self) -> Result<PixelBuffer, RendererError>;
+
+    /// Copy the base layer back as the backend has it, i.e. with what
+    /// compute shaders did to it, in the alpha mode of the base layer.
+    /// Resolves once the copy is done, see `poll`.
+    fn read_canvas(&self, stack: &LayerStack) -> CanvasReadback;
+
+    /// The frame the last `render` showed, read back whatever the target
+    /// is. Drawn again rather than rerunning compute and shadertoy shaders.
+    fn capture_frame(
+        &mut self,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+    ) -> Result<PixelBuffer, RendererError>;
+
+    /// Finish up gpu work that is done, like `read_canvas` copies. `wait`
+    /// blocks until everything submitted so far is done.
+    fn poll(&self, wait: bool);
+
+    /// Bytes uploaded by the last `sync_layers`
+    fn upload_bytes(&self) -> u64;
+
+    /// Test hook: make the next frame fail as if the surface returned
+    /// `error`
+    fn inject_surface_error(&mut self, error: wgpu::SwapChainError);
+
+    fn surface_stats(&self) -> SurfaceStats;
+
+    /// The adapter doing the work, `None` if it's not a gpu
+    fn adapter_info(&self) -> Option<wgpu::AdapterInfo>;
+
+    fn filter(&self) -> FilterMode;
+
+    /// Layers may have to be uploaded again by the next `sync_layers`
+    fn set_filter(&mut self, filter: FilterMode);
+
+    fn post_effects(&self) -> &[PostEffect];
+
+    /// Replace the post processing passes, they run in order after the
+    /// layers are drawn
+    fn set_post_effects(&mut self, effects: &[PostEffect]) -> Result<(), RendererError>;
+
+    /// Draw the output of a fragment shader in place of the base layer,
+    /// `None` to go back to the base layer
+    fn set_shadertoy(&mut self, shader: Option<&[u8]>) -> Result<(), RendererError>;
+
+    /// `iMouse` for the shadertoy shader
+    fn set_shadertoy_mouse(&mut self, mouse: [f32; 4]);
+
+    /// Run a compute shader on the base layer before every frame
+    fn add_compute(&mut self, shader: &ComputeShader) -> Result<ComputeId, RendererError>;
+
+    fn remove_compute(&mut self, id: ComputeId);
+
+    fn set_compute_uniforms(&mut self, id: ComputeId, uniforms: &[u8])
+        -> Result<(), RendererError>;
+
+    /// Recompile shaders that changed on disk, see `RendererConfig::hot_reload`
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) -> Vec<ShaderError> {
+        Vec::new()
+    }
+}
+
 // -----------------------------------------------------------------------------
 //     - Renderer -
 // -----------------------------------------------------------------------------
 pub struct Renderer {
-    pixels: PixelBuffer,
-    state: State,
+    layers: LayerStack,
+    backend: Box<dyn PresentBackend>,
+    pacer: FramePacer,
+    scaling_mode: ScalingMode,
+    /// Fills the parts of the window not covered by the canvas
+    letterbox_color: Pixel,
+    shadertoy_mouse: ShadertoyMouse,
+    recorder: FrameRecorder,
+    /// Set when a `draw` couldn't write to the recording
+    recording_error: Option<RendererError>,
 }
 
+// Headless and software renderers, readback and settings are for callers
+// other than the windowed demo
+#[allow(dead_code)]
 impl Renderer {
+    /// Pixels of the base layer
     pub fn pixels(&mut self) -> &mut PixelBuffer {
-        &mut self.pixels
+        &mut self.layers.base_mut().pixels
     }
 
-    pub fn draw(&mut self) {
-        let layer = &self.state.layers[0];
+    pub fn layers(&self) -> &LayerStack {
+        &self.layers
+    }
 
-        self.state.queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &layer.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &self.pixels,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * layer.width,
-                rows_per_image: layer.height,
-            },
-            layer.texture_size,
-        );
+    /// Add, remove and reorder layers
+    pub fn layers_mut(&mut self) -> &mut LayerStack {
+        &mut self.layers
     }
 
-    pub fn render(&mut self) {
-        self.state.render();
+    /// Upload the regions of every layer that changed since the last call.
+    /// The base layer is then a frame for the recorder, if writing it fails
+    /// the recording stops rather than failing the draw, see
+    /// `take_recording_error`.
+    pub fn draw(&mut self) -> Result<(), RendererError> {
+        self.sync_layers()?;
+        if let Err(e) = self.recorder.capture(&self.layers.base().pixels) {
+            self.recording_error = Some(e);
+        }
+        Ok(())
     }
 
-    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        self.state.resize(new_size);
+    /// Upload the layers without recording a frame, for changes that
+    /// aren't frames of their own
+    fn sync_layers(&mut self) -> Result<(), RendererError> {
+        self.backend.sync_layers(&mut self.layers)
     }
 
-    pub fn new(w: usize, h: usize, window: &Window) -> Self {
-        let pixel_count = w * h;
+    /// Why the recording stopped by itself, if it did since the last call
+    pub fn take_recording_error(&mut self) -> Option<RendererError> {
+        self.recording_error.take()
+    }
 
-        Self {
-            pixels: PixelBuffer::with_capacity(pixel_count),
-            state: block_on(State::new(window)),
-        }
+    /// Write every frame from the next `draw` on to `output`, until
+    /// `stop_recording`. Stops the recording that was already going.
+    pub fn start_recording(&mut self, output: RecordOutput) -> Result<(), RendererError> {
+        self.recorder.start(output)
     }
-}
 
-// -----------------------------------------------------------------------------
-//     - State-
-//     Maybe absolute nonsense:
-//     Device -> [ Queue -> SwapChain -> RenderPipeline -> Surface ]
-// -----------------------------------------------------------------------------
-struct State {
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
-    size: PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    diffuse_bind_group: wgpu::BindGroup,
-    layers: Vec<Layer>,
-}
+    pub fn stop_recording(&mut self) -> Result<(), RendererError> {
+        self.recorder.stop()
+    }
 
-impl State {
-    async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
-        let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .unwrap();
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_recording()
+    }
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    shader_validation: true,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+    /// Write the last `RendererConfig::record_history` seconds of frames to
+    /// `output`, whether or not they were being recorded
+    pub fn save_recent(&self, output: RecordOutput) -> Result<(), RendererError> {
+        self.recorder.save_history(output)
+    }
 
-        let sc_desc = wgpu::SwapChainDescriptor {
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
+    /// Number of bytes the last `draw` uploaded to the backend
+    pub fn upload_bytes(&self) -> u64 {
+        self.backend.upload_bytes()
+    }
 
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+    pub fn render(&mut self) -> Result<(), RendererError> {
+        let viewport = self.viewport();
+        let presented = self.backend.surface_stats().presented;
+        self.backend
+            .render(&self.layers, viewport, self.letterbox_color)?;
 
-        // -----------------------------------------------------------------------------
-        //     - Texture -
-        // -----------------------------------------------------------------------------
-        // let diffuse_bytes = include_bytes!("../textures/supertexture.png");
-        // let diffuse_image = image::load_from_memory(diffuse_bytes).unwrap();
-        // let diffuse_rgba = diffuse_image.as_rgba8().unwrap();
-        // let diffuse_rgba = red().into_iter().map(|p| p.bytes().into_iter()).flatten().collect::<Vec<_>>();
+        // Skipped frames don't count
+        if self.backend.surface_stats().presented > presented {
+            self.pacer.frame_presented(Instant::now());
+        }
 
-        let diffuse_rgba = PixelBuffer::with_capacity(128 * 128);
+        Ok(())
+    }
 
-        use image::GenericImageView;
-        let (width, height) = (128, 128); // diffuse_image.dimensions();
-        let texture_size = wgpu::Extent3d {
-            width,
-            height,
-            depth: 1,
-        };
+    /// False if rendering now would go over the target frame rate
+    pub fn frame_due(&self) -> bool {
+        self.pacer.is_due(Instant::now())
+    }
 
-        let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: Some("omg textures!!!!"),
-        });
+    /// Set this on the event loop after each frame to wait for the next
+    pub fn control_flow(&self) -> ControlFlow {
+        self.pacer.control_flow()
+    }
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &diffuse_rgba,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * width,
-                rows_per_image: height,
-            },
-            texture_size,
-        );
+    /// Frame times over the last couple of frames, and dropped frames
+    pub fn frame_stats(&self) -> FrameStats {
+        self.pacer.stats()
+    }
 
-        let diffuse_texture_view =
-            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    /// Also call this when the scale factor changes, the viewport follows
+    /// the new size. Frames are skipped while either side is zero, i.e.
+    /// the window is minimized.
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.backend.resize(new_size);
+    }
 
-        let layer_one = Layer {
-            width,
-            height,
-            texture: diffuse_texture,
-            texture_size: wgpu::Extent3d {
-                width: width,
-                height: height,
-                depth: 1,
-            },
-        };
+    /// Test hook: make the next `render` behave as if the surface returned
+    /// `error`. Timeouts skip the frame, outdated and lost surfaces skip it
+    /// and recreate the swap chain for the next one, running out of memory
+    /// is an error.
+    pub fn inject_surface_error(&mut self, error: wgpu::SwapChainError) {
+        self.backend.inject_surface_error(error);
+    }
 
-        let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+    /// Name, backend and type of the adapter in use, `None` for the
+    /// software backend
+    pub fn adapter_info(&self) -> Option<wgpu::AdapterInfo> {
+        self.backend.adapter_info()
+    }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::SampledTexture {
-                            multisampled: false,
-                            dimension: wgpu::TextureViewDimension::D2,
-                            component_type: wgpu::TextureComponentType::Uint,
+    /// Presented, skipped and recreated frame counts
+    pub fn surface_stats(&self) -> SurfaceStats {
+        self.backend.surface_stats()
+    }
+
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    pub fn set_scaling_mode(&mut self, mode: ScalingMode) {
+        self.scaling_mode = mode;
+    }
+
+    pub fn letterbox_color(&self) -> Pixel {
+        self.letterbox_color
+    }
+
+    /// Colour around the canvas when it doesn't fill the window. Alpha is
+    /// ignored, the frame is always opaque.
+    pub fn set_letterbox_color(&mut self, color: Pixel) {
+        self.letterbox_color = color;
+    }
+
+    pub fn filter(&self) -> FilterMode {
+        self.backend.filter()
+    }
+
+    /// Switching to or from trilinear recreates the layer textures, so
+    /// every layer is uploaded again.
+    pub fn set_filter(&mut self, filter: FilterMode) -> Result<(), RendererError> {
+        self.backend.set_filter(filter);
+        self.sync_layers()
+    }
+
+    pub fn post_effects(&self) -> &[PostEffect] {
+        self.backend.post_effects()
+    }
+
+    /// Full screen passes that run, in order, between the layers and the
+    /// window. They see the whole window, letterbox included.
+    pub fn set_post_effects(&mut self, effects: &[PostEffect]) -> Result<(), RendererError> {
+        self.backend.set_post_effects(effects)
+    }
+
+    /// Shadertoy mode: every frame `shader` runs over the whole canvas and
+    /// what it outputs is drawn instead of the base layer. The base layer
+    /// is still uploaded, the shader gets it as `iChannel0`. See
+    /// `shadertoy/trails.frag` for what else it gets. Fails, keeping the
+    /// shader that was set, if `shader` isn't SPIR-V.
+    pub fn set_shadertoy(&mut self, shader: Option<&[u8]>) -> Result<(), RendererError> {
+        self.backend.set_shadertoy(shader)
+    }
+
+    /// Call once a frame while in shadertoy mode to keep `iMouse` up to
+    /// date. `position` is the canvas pixel under the cursor, see
+    /// `window_to_canvas`.
+    pub fn update_shadertoy_mouse(&mut self, position: Option<(u32, u32)>, pressed: bool) {
+        self.shadertoy_mouse.update(position, pressed);
+        self.backend
+            .set_shadertoy_mouse(self.shadertoy_mouse.uniform());
+    }
+
+    /// Run `shader` on the base layer every frame, before it's drawn.
+    /// Shaders run in the order they were added, each one sees what the
+    /// one before wrote. What they write stays on the gpu, the
+    /// `PixelBuffer` doesn't change and its dirty rects still overwrite
+    /// the texture.
+    pub fn add_compute(&mut self, shader: &ComputeShader) -> Result<ComputeId, RendererError> {
+        self.backend.add_compute(shader)
+    }
+
+    pub fn remove_compute(&mut self, id: ComputeId) {
+        self.backend.remove_compute(id);
+    }
+
+    /// Replace the uniforms of a compute shader, used from the next frame
+    pub fn set_compute_uniforms(
+        &mut self,
+        id: ComputeId,
+        uniforms: &[u8],
+    ) -> Result<(), RendererError> {
+        self.backend.set_compute_uniforms(id, uniforms)
+    }
+
+    /// Rebuild the pipelines whose shaders changed on disk since the last
+    /// call, if hot reloading is on. Shaders that fail to compile are
+    /// returned and the last good ones stay in use.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self) -> Vec<ShaderError> {
+        self.backend.reload_shaders()
+    }
+
+    /// Where the canvas is drawn in the window, for the current window
+    /// size, canvas size and scaling mode.
+    pub fn viewport(&self) -> Viewport {
+        let size = self.backend.size();
+        self.scaling_mode
+            .viewport(self.layers.size(), (size.width, size.height))
+    }
+
+    /// The frame from the last `render`, as it would be shown in the
+    /// window. Frames rendered to a window can't be read back.
+    pub fn read_frame(&self) -> Result<PixelBuffer, RendererError> {
+        self.backend.read_frame()
+    }
+
+    /// What the window showed after the last `render`, with the scaling,
+    /// letterbox and post processing. Works for windows too, unlike
+    /// `read_frame`. Fails while the window has no size, e.g. minimized.
+    pub fn capture_frame(&mut self) -> Result<PixelBuffer, RendererError> {
+        let viewport = self.viewport();
+        self.backend
+            .capture_frame(&self.layers, viewport, self.letterbox_color)
+    }
+
+    /// Save `capture_frame` as an image, the format comes from the
+    /// extension of `path`, e.g. ".png"
+    pub fn capture_screenshot(&mut self, path: impl AsRef<Path>) -> Result<(), RendererError> {
+        self.capture_frame()?.save(path)?;
+        Ok(())
+    }
+
+    /// The base layer as the gpu has it, including what compute shaders
+    /// did to it. Blocks until the copy is done.
+    pub fn read_canvas(&self) -> Result<PixelBuffer, RendererError> {
+        let readback = self.backend.read_canvas(&self.layers);
+        self.backend.poll(true);
+        block_on(readback)
+    }
+
+    /// `read_canvas` without blocking. The future resolves once the gpu is
+    /// done with the copy, which is noticed by the next `render` or `poll`.
+    pub fn read_canvas_async(&self) -> CanvasReadback {
+        self.backend.read_canvas(&self.layers)
+    }
+
+    /// Check for finished gpu work without waiting, to move
+    /// `read_canvas_async` along when not rendering
+    pub fn poll(&self) {
+        self.backend.poll(false);
+    }
+
+    /// The canvas pixel at a position in the window, taking scaling and
+    /// letterboxing into account. `None` on the letterbox bars.
+    pub fn window_to_canvas(&self, position: PhysicalPosition<f64>) -> Option<(u32, u32)> {
+        self.viewport()
+            .to_canvas(position.x, position.y, self.layers.size())
+    }
+
+    /// Change the canvas size, existing pixels are kept where they fit and
+    /// cropped where they don't.
+    pub fn resize_canvas(&mut self, w: u32, h: u32) -> Result<(), RendererError> {
+        check_canvas_size(w, h)?;
+        let base = self.layers.base_mut();
+        base.pixels = base.pixels.resized(w, h);
+        self.sync_layers()
+    }
+
+    pub fn new(w: u32, h: u32, window: &Window) -> Result<Self, RendererError> {
+        Self::with_config(w, h, window, RendererConfig::default())
+    }
+
+    pub fn with_config(
+        w: u32,
+        h: u32,
+        window: &Window,
+        config: RendererConfig,
+    ) -> Result<Self, RendererError> {
+        check_canvas_size(w, h)?;
+        let state = block_on(State::new(window, &config))?;
+        let mut renderer = Self::with_backend(w, h, Box::new(state), &config)?;
+        renderer.pacer = FramePacer::new(config.target_fps);
+        Ok(renderer)
+    }
+
+    /// A renderer without a window. Frames are rendered to an `output_w`
+    /// by `output_h` texture, use `read_frame` to get at them. The output
+    /// has the same size limits as the canvas.
+    pub fn new_headless(
+        w: u32,
+        h: u32,
+        output_w: u32,
+        output_h: u32,
+    ) -> Result<Self, RendererError> {
+        Self::headless_with_config(w, h, output_w, output_h, RendererConfig::default())
+    }
+
+    /// `new_headless` with a choice of adapter. The present mode and
+    /// frame rate don't matter without a window.
+    pub fn headless_with_config(
+        w: u32,
+        h: u32,
+        output_w: u32,
+        output_h: u32,
+        config: RendererConfig,
+    ) -> Result<Self, RendererError> {
+        check_canvas_size(w, h)?;
+        check_output_size(output_w, output_h)?;
+        let size = PhysicalSize::new(output_w, output_h);
+        let state = block_on(State::new_headless(size, &config))?;
+        Self::with_backend(w, h, Box::new(state), &config)
+    }
+
+    /// Like `new_headless` but without a gpu, everything is done on the
+    /// cpu.
+    pub fn new_software(
+        w: u32,
+        h: u32,
+        output_w: u32,
+        output_h: u32,
+    ) -> Result<Self, RendererError> {
+        Self::software_with_config(w, h, output_w, output_h, RendererConfig::default())
+    }
+
+    /// `new_software` with a canvas format, filter and recording settings,
+    /// the rest of the config doesn't apply to the cpu.
+    pub fn software_with_config(
+        w: u32,
+        h: u32,
+        output_w: u32,
+        output_h: u32,
+        config: RendererConfig,
+    ) -> Result<Self, RendererError> {
+        check_output_size(output_w, output_h)?;
+        let size = PhysicalSize::new(output_w, output_h);
+        let backend = SoftwareBackend::new(size, config.canvas_format, config.filter);
+        Self::with_backend(w, h, Box::new(backend), &config)
+    }
+
+    /// A renderer drawing with any backend. Only the settings that don't
+    /// depend on the backend, like scaling and recording, are taken from
+    /// `config`.
+    pub fn with_backend(
+        w: u32,
+        h: u32,
+        mut backend: Box<dyn PresentBackend>,
+        config: &RendererConfig,
+    ) -> Result<Self, RendererError> {
+        check_canvas_size(w, h)?;
+        let mut layers = LayerStack::new(w, h);
+        backend.sync_layers(&mut layers)?;
+
+        Ok(Self {
+            layers,
+            backend,
+            pacer: FramePacer::new(None),
+            scaling_mode: config.scaling_mode,
+            letterbox_color: Pixel::black(),
+            shadertoy_mouse: ShadertoyMouse::default(),
+            recorder: FrameRecorder::new(config.record_fps, config.record_history),
+            recording_error: None,
+        })
+    }
+}
+
+/// Sources of the layer shaders, watched when hot reloading
+#[cfg(feature = "hot-reload")]
+const LAYER_VERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.vert");
+#[cfg(feature = "hot-reload")]
+const LAYER_FRAG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.frag");
+
+// -----------------------------------------------------------------------------
+//     - State-
+//     Maybe absolute nonsense:
+//     Device -> [ Queue -> SwapChain -> RenderPipeline -> Surface ]
+// -----------------------------------------------------------------------------
+struct State {
+    target: RenderTarget,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Size and format of the target, also used for the offscreen texture
+    sc_desc: wgpu::SwapChainDescriptor,
+    size: PhysicalSize<u32>,
+    canvas_format: CanvasFormat,
+    surface_state: SurfaceState,
+    adapter_info: wgpu::AdapterInfo,
+    /// One for each blend mode
+    pipelines: HashMap<BlendMode, wgpu::RenderPipeline>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    filter: FilterMode,
+    diffuse_sampler: wgpu::Sampler,
+    mipmaps: MipmapGenerator,
+    post: PostChain,
+    shadertoy: Option<Shadertoy>,
+    compute: ComputePasses,
+    /// `None` unless hot reloading
+    #[cfg(feature = "hot-reload")]
+    shaders: Option<ShaderWatcher>,
+    layers: Vec<Layer>,
+    /// Bytes written to textures by the last `sync_layers`
+    upload_bytes: u64,
+}
+
+impl State {
+    async fn new(window: &Window, config: &RendererConfig) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(config.backends);
+        let surface = unsafe { instance.create_surface(window) };
+        Self::with_surface(instance, Some(surface), window.inner_size(), config).await
+    }
+
+    /// Render to a texture instead of a window
+    async fn new_headless(
+        size: PhysicalSize<u32>,
+        config: &RendererConfig,
+    ) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(config.backends);
+        Self::with_surface(instance, None, size, config).await
+    }
+
+    async fn with_surface(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        size: PhysicalSize<u32>,
+        config: &RendererConfig,
+    ) -> Result<Self, RendererError> {
+        let size = clamp_size(size);
+
+        // Without a surface any adapter will do, including software ones
+        let adapter = select_adapter(&instance, config, surface.as_ref()).await?;
+        let adapter_info = adapter.get_info();
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::default(),
+                    shader_validation: true,
+                },
+                None,
+            )
+            .await?;
+
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: config.present_mode,
+        };
+
+        // The swap chain is created on the first frame
+        let target = match surface {
+            Some(surface) => RenderTarget::Window {
+                surface,
+                swap_chain: None,
+            },
+            None => RenderTarget::offscreen(&device, &sc_desc),
+        };
+
+        // -----------------------------------------------------------------------------
+        //     - Texture -
+        // -----------------------------------------------------------------------------
+        let diffuse_sampler = device.create_sampler(&config.filter.sampler_descriptor());
+        let mipmaps = MipmapGenerator::new(
+            &device,
+            config.canvas_format.texture_format(),
+            config.canvas_format.component_type(),
+        );
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: config.canvas_format.component_type(),
                         },
                         count: None,
                     },
@@ -332,25 +1180,19 @@ impl State {
                         ty: wgpu::BindingType::Sampler { comparison: false },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture binding group layout"),
             });
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                },
-            ],
-            label: Some("meh"),
-        });
-
         // -----------------------------------------------------------------------------
         //     - Shader bits -
         // -----------------------------------------------------------------------------
@@ -370,74 +1212,290 @@ impl State {
             usage: wgpu::BufferUsage::INDEX,
         });
 
+        let mut post = PostChain::new(&device, sc_desc.format);
+        post.resize(&device, size);
+
         // -----------------------------------------------------------------------------
         //     - Pipeline -
         // -----------------------------------------------------------------------------
-        let render_pipeline = create_pipeline(
+        let pipelines = create_pipelines(
             &device,
             &sc_desc,
-            vs_module,
-            fs_module,
-            texture_bind_group_layout,
+            &vs_module,
+            &fs_module,
+            &texture_bind_group_layout,
         );
 
-        Self {
-            surface,
+        #[cfg(feature = "hot-reload")]
+        let shaders = match config.hot_reload {
+            true => ShaderWatcher::new().map(|mut shaders| {
+                shaders.watch(LAYER_VERT_PATH);
+                shaders.watch(LAYER_FRAG_PATH);
+                shaders
+            }),
+            false => None,
+        };
+
+        Ok(Self {
+            target,
             device,
             queue,
             sc_desc,
-            swap_chain,
             size,
-            render_pipeline,
+            canvas_format: config.canvas_format,
+            surface_state: SurfaceState::new(),
+            adapter_info,
+            pipelines,
             vertex_buffer,
             index_buffer,
             num_indices: INDICES.len() as u32,
-            diffuse_bind_group,
-            layers: vec![layer_one],
+            texture_bind_group_layout,
+            filter: config.filter,
+            diffuse_sampler,
+            mipmaps,
+            post,
+            shadertoy: None,
+            compute: ComputePasses::new(config.canvas_format.storage_format()),
+            #[cfg(feature = "hot-reload")]
+            shaders,
+            layers: Vec::new(),
+            upload_bytes: 0,
+        })
+    }
+
+    /// Upload the dirty parts of every layer, creating textures for new
+    /// and resized layers. A layer's dirty rects are only cleared once all
+    /// of them are uploaded, so a failed upload is retried by the next
+    /// sync.
+    fn upload_layers(
+        &mut self,
+        stack: &mut LayerStack,
+        remip: &mut Vec<usize>,
+    ) -> Result<(), RendererError> {
+        for canvas_layer in stack.iter_mut() {
+            let (width, height) = (canvas_layer.pixels.width(), canvas_layer.pixels.height());
+            check_canvas_size(width, height)?;
+            let existing = self.layers.iter().position(|l| l.id == canvas_layer.id());
+
+            let (index, fresh) = match existing {
+                Some(i) if self.layers[i].width == width && self.layers[i].height == height => {
+                    (i, false)
+                }
+                existing => {
+                    let layer = Layer::new(self, canvas_layer.id(), width, height);
+                    match existing {
+                        Some(i) => {
+                            self.layers[i] = layer;
+                            (i, true)
+                        }
+                        None => {
+                            self.layers.push(layer);
+                            (self.layers.len() - 1, true)
+                        }
+                    }
+                }
+            };
+
+            let dirty = match fresh {
+                true => vec![canvas_layer.pixels.bounds()],
+                false => canvas_layer.pixels.dirty_rects().to_vec(),
+            };
+
+            let mut bytes = 0;
+            for &rect in &dirty {
+                match self.layers[index].upload(&self.queue, &canvas_layer.pixels, rect) {
+                    Ok(n) => bytes += n,
+                    Err(e) => {
+                        // The new texture has nothing in it yet
+                        if fresh {
+                            canvas_layer.pixels.mark_all_dirty();
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+
+            canvas_layer.pixels.take_dirty();
+            self.upload_bytes += bytes;
+            if !dirty.is_empty() && !self.layers[index].mips.is_empty() {
+                remip.push(index);
+            }
         }
+
+        Ok(())
     }
 
-    fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        self.size = new_size;
-        self.sc_desc.width = new_size.width;
-        self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+    fn write_layer_uniforms(&self, stack: &LayerStack, viewport: Viewport) {
+        let canvas_size = stack.size();
+        let window_size = (self.size.width, self.size.height);
+        for canvas_layer in stack.iter() {
+            if let Some(layer) = self.layers.iter().find(|l| l.id == canvas_layer.id()) {
+                let uniforms = LayerUniforms::new(
+                    canvas_layer,
+                    canvas_size,
+                    viewport,
+                    window_size,
+                    self.filter,
+                );
+                self.queue
+                    .write_buffer(&layer.uniforms, 0, bytemuck::bytes_of(&uniforms));
+            }
+        }
+    }
+
+    /// The last output of the shadertoy shader, bound like `base` so it can
+    /// be drawn in its place. `None` without a shadertoy shader.
+    fn shadertoy_bind_group(&self, base: &Layer) -> Option<wgpu::BindGroup> {
+        let output = self.shadertoy.as_ref()?.output()?;
+        Some(create_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            output,
+            &self.diffuse_sampler,
+            &base.uniforms,
+        ))
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+    /// Record drawing the layers into `view`, as of the last
+    /// `write_layer_uniforms`
+    fn draw_layers(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+        view: &wgpu::TextureView,
+        shadertoy_bind_group: Option<&wgpu::BindGroup>,
+    ) {
+        let window_size = (self.size.width, self.size.height);
+        let base_id = stack.base_id();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color(letterbox_color)),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        // Keep layers that stick out past the canvas off the letterbox,
+        // and draw nothing if the canvas isn't in the window at all
+        let visible = viewport.visible(window_size);
+        if let Some((x, y, width, height)) = visible {
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..));
+
+        // Bottom to top
+        let layers = stack.iter().filter(|l| l.visible && visible.is_some());
+        for canvas_layer in layers {
+            let layer = match self.layers.iter().find(|l| l.id == canvas_layer.id()) {
+                Some(layer) => layer,
+                None => continue,
+            };
+
+            let bind_group = match shadertoy_bind_group {
+                Some(bind_group) if layer.id == base_id => bind_group,
+                _ => &layer.bind_group,
+            };
+
+            render_pass.set_pipeline(&self.pipelines[&canvas_layer.blend_mode]);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        }
     }
+}
+
+impl PresentBackend for State {
+    /// Create, recreate and drop textures so there is one of the right
+    /// size for every layer in the stack, and upload the dirty ones.
+    fn sync_layers(&mut self, stack: &mut LayerStack) -> Result<(), RendererError> {
+        self.upload_bytes = 0;
+        self.layers.retain(|layer| stack.get(layer.id).is_some());
+        // Layers whose mip levels are out of date
+        let mut remip = Vec::new();
 
-    fn draw(&mut self) {
-        // let diffuse_rgba = PixelBuffer::with_capacity();
-        // let mut diffuse_rgba = blue().into_iter().map(|p| p.bytes()).flatten().collect::<Vec<_>>();
-        // let index = diffuse_rgba.len() / 2;
-        // diffuse_rgba.iter_mut().skip(index).map(|p| *p = 255).collect::<Vec<_>>();
+        // Layers uploaded before a failure still get their mips
+        let uploaded = self.upload_layers(stack, &mut remip);
+
+        // Texture writes happen before the next submit, so the mips are
+        // made from the new pixels
+        if !remip.is_empty() {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("mipmaps"),
+                });
+            for index in remip {
+                self.mipmaps
+                    .generate(&mut encoder, &self.layers[index].mips);
+            }
+            self.queue.submit(std::iter::once(encoder.finish()));
+        }
 
-        // let layer = &self.layers[0];
+        uploaded
+    }
+
+    /// The swap chain is only recreated when the next frame is rendered,
+    /// so resizing many times in a row is cheap.
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        let size = clamp_size(new_size);
+        self.size = size;
+        self.sc_desc.width = size.width;
+        self.sc_desc.height = size.height;
+        self.surface_state.invalidate();
+        self.post.resize(&self.device, size);
 
-        // self.queue.write_texture(
-        //     wgpu::TextureCopyView {
-        //         texture: &layer.texture,
-        //         mip_level: 0,
-        //         origin: wgpu::Origin3d::ZERO,
-        //     },
-        //     diffuse_rgba.as_slice(),
-        //     wgpu::TextureDataLayout {
-        //         offset: 0,
-        //         bytes_per_row: 4 * layer.width,
-        //         rows_per_image: layer.height,
-        //     },
-        //     layer.texture_size,
-        // );
+        if let RenderTarget::Window { .. } = self.target {
+            return;
+        }
+        self.target = RenderTarget::offscreen(&self.device, &self.sc_desc);
     }
 
-    fn render(&mut self) {
+    fn render(
+        &mut self,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+    ) -> Result<(), RendererError> {
+        self.write_layer_uniforms(stack, viewport);
+
+        let (target, device, sc_desc) = (&mut self.target, &self.device, &self.sc_desc);
         let frame = self
-            .swap_chain
-            .get_current_frame()
-            .expect("Timeout?")
-            .output;
+            .surface_state
+            .next_frame(self.size, |recreate| match target {
+                RenderTarget::Window {
+                    surface,
+                    swap_chain,
+                } => {
+                    // Drop the old one first, a surface can only have one
+                    if recreate {
+                        *swap_chain = None;
+                    }
+                    swap_chain
+                        .get_or_insert_with(|| device.create_swap_chain(surface, sc_desc))
+                        .get_current_frame()
+                        .map(Some)
+                }
+                _ => Ok(None),
+            })?;
+
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let view = match (&frame, &self.target) {
+            (Some(frame), _) => &frame.output.view,
+            (None, RenderTarget::Offscreen { view, .. }) => view,
+            _ => unreachable!("frames are skipped while the target is empty"),
+        };
 
         let mut encoder = self
             .device
@@ -445,60 +1503,451 @@ impl State {
                 label: Some("I haz label"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
+        let base_id = stack.base_id();
+        let base = self.layers.iter().find(|l| l.id == base_id);
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..));
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        if let (Some(base), false) = (base, self.compute.is_empty()) {
+            let canvas_size = (base.width, base.height);
+            self.compute
+                .run(&self.device, &mut encoder, &base.texture, canvas_size);
+            if !base.mips.is_empty() {
+                self.mipmaps.generate(&mut encoder, &base.mips);
+            }
         }
 
+        // Runs before the layers are drawn, and is then drawn instead of
+        // the base layer
+        if let (Some(shadertoy), Some(base)) = (&mut self.shadertoy, base) {
+            let canvas_size = (base.width, base.height);
+            shadertoy.run(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &base.texture,
+                canvas_size,
+            );
+        }
+        let shadertoy_bind_group = base.and_then(|base| self.shadertoy_bind_group(base));
+
+        // With post processing the layers are drawn into its first texture
+        // instead
+        self.draw_layers(
+            &mut encoder,
+            stack,
+            viewport,
+            letterbox_color,
+            self.post.input().unwrap_or(view),
+            shadertoy_bind_group.as_ref(),
+        );
+
+        self.post.run(&self.queue, &mut encoder, view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+
+    /// Copy the last rendered frame of an offscreen target back to the cpu.
+    /// Swap chain frames can't be read.
+    fn read_frame(&self) -> Result<PixelBuffer, RendererError> {
+        let texture = match &self.target {
+            RenderTarget::Offscreen { texture, .. } => texture,
+            RenderTarget::Empty => return Ok(PixelBuffer::new(0, 0)),
+            RenderTarget::Window { .. } => return Err(RendererError::ReadbackUnsupported),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("frame readback"),
+            });
+
+        let size = (self.sc_desc.width, self.sc_desc.height);
+        let readback = Readback::new(&self.device, &mut encoder, texture, size, 4);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let pixels = readback.pixels(bgra_to_pixel);
+        self.device.poll(wgpu::Maintain::Wait);
+        block_on(pixels)
+    }
+
+    /// Copies the texture as of the last `render`, or the last
+    /// `sync_layers` when there are no compute shaders
+    fn read_canvas(&self, stack: &LayerStack) -> CanvasReadback {
+        let base = match self.layers.iter().find(|l| l.id == stack.base_id()) {
+            Some(base) => base,
+            // Not uploaded yet, so the pixels are all there is
+            None => return Box::pin(future::ready(Ok(stack.base().pixels.clone()))),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("canvas readback"),
+            });
+
+        let format = self.canvas_format;
+        let alpha_mode = stack.base().pixels.alpha_mode();
+        let readback = Readback::new(
+            &self.device,
+            &mut encoder,
+            &base.texture,
+            (base.width, base.height),
+            format.bytes_per_pixel(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let pixels = readback.pixels(move |bytes| format.read_pixel(bytes));
+        Box::pin(async move {
+            let mut pixels = pixels.await?;
+            pixels.set_alpha_mode(alpha_mode);
+            Ok(pixels)
+        })
+    }
+
+    /// Swap chain frames can't be copied, so the layers and post processing
+    /// are drawn again into a texture that can, with the same post
+    /// processing time and frame as the last frame.
+    fn capture_frame(
+        &mut self,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+    ) -> Result<PixelBuffer, RendererError> {
+        let (texture, view) = match RenderTarget::offscreen(&self.device, &self.sc_desc) {
+            RenderTarget::Offscreen { texture, view } => (texture, view),
+            // A minimized window has nothing to capture
+            _ => {
+                return Err(RendererError::InvalidOutputSize {
+                    width: self.sc_desc.width,
+                    height: self.sc_desc.height,
+                })
+            }
+        };
+
+        self.write_layer_uniforms(stack, viewport);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("frame capture"),
+            });
+
+        let base = self.layers.iter().find(|l| l.id == stack.base_id());
+        let shadertoy_bind_group = base.and_then(|base| self.shadertoy_bind_group(base));
+        self.draw_layers(
+            &mut encoder,
+            stack,
+            viewport,
+            letterbox_color,
+            self.post.input().unwrap_or(&view),
+            shadertoy_bind_group.as_ref(),
+        );
+        self.post.rerun(&mut encoder, &view);
+
+        let size = (self.sc_desc.width, self.sc_desc.height);
+        let readback = Readback::new(&self.device, &mut encoder, &texture, size, 4);
         self.queue.submit(std::iter::once(encoder.finish()));
+
+        let pixels = readback.pixels(bgra_to_pixel);
+        self.device.poll(wgpu::Maintain::Wait);
+        block_on(pixels)
+    }
+
+    fn poll(&self, wait: bool) {
+        let maintain = match wait {
+            true => wgpu::Maintain::Wait,
+            false => wgpu::Maintain::Poll,
+        };
+        self.device.poll(maintain);
+    }
+
+    fn size(&self) -> PhysicalSize<u32> {
+        self.size
     }
+
+    fn upload_bytes(&self) -> u64 {
+        self.upload_bytes
+    }
+
+    fn inject_surface_error(&mut self, error: wgpu::SwapChainError) {
+        self.surface_state.inject_error(error);
+    }
+
+    fn surface_stats(&self) -> SurfaceStats {
+        self.surface_state.stats()
+    }
+
+    fn adapter_info(&self) -> Option<wgpu::AdapterInfo> {
+        Some(self.adapter_info.clone())
+    }
+
+    fn filter(&self) -> FilterMode {
+        self.filter
+    }
+
+    /// Every layer is recreated with the new sampler, and the right number
+    /// of mip levels
+    fn set_filter(&mut self, filter: FilterMode) {
+        self.filter = filter;
+        self.diffuse_sampler = self.device.create_sampler(&filter.sampler_descriptor());
+        self.layers.clear();
+    }
+
+    fn post_effects(&self) -> &[PostEffect] {
+        self.post.effects()
+    }
+
+    fn set_post_effects(&mut self, effects: &[PostEffect]) -> Result<(), RendererError> {
+        self.post.set_effects(&self.device, effects)?;
+
+        #[cfg(feature = "hot-reload")]
+        if let Some(shaders) = &mut self.shaders {
+            effects
+                .iter()
+                .filter_map(|effect| effect.source_path())
+                .for_each(|path| shaders.watch(path));
+        }
+
+        Ok(())
+    }
+
+    fn set_shadertoy(&mut self, shader: Option<&[u8]>) -> Result<(), RendererError> {
+        self.shadertoy = match shader {
+            Some(spirv) => Some(Shadertoy::new(&self.device, spirv)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn set_shadertoy_mouse(&mut self, mouse: [f32; 4]) {
+        if let Some(shadertoy) = &mut self.shadertoy {
+            shadertoy.set_mouse(mouse);
+        }
+    }
+
+    fn add_compute(&mut self, shader: &ComputeShader) -> Result<ComputeId, RendererError> {
+        self.compute.add(&self.device, shader)
+    }
+
+    fn remove_compute(&mut self, id: ComputeId) {
+        self.compute.remove(id);
+    }
+
+    fn set_compute_uniforms(
+        &mut self,
+        id: ComputeId,
+        uniforms: &[u8],
+    ) -> Result<(), RendererError> {
+        self.compute.set_uniforms(&self.queue, id, uniforms)
+    }
+
+    /// Shaders that compile but don't fit the pipeline layout make wgpu
+    /// panic, that's caught and the old pipeline is kept
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) -> Vec<ShaderError> {
+        let shaders = match &mut self.shaders {
+            Some(shaders) => shaders,
+            None => return Vec::new(),
+        };
+
+        let changed = shaders.changed();
+        let is_changed = |path: &str| changed.iter().any(|c| c == Path::new(path));
+        let mut errors = Vec::new();
+
+        // Both are needed to build the pipelines
+        if is_changed(LAYER_VERT_PATH) || is_changed(LAYER_FRAG_PATH) {
+            let vs = shaders.compile(Path::new(LAYER_VERT_PATH));
+            let fs = shaders.compile(Path::new(LAYER_FRAG_PATH));
+            match (vs, fs) {
+                (Ok(vs), Ok(fs)) => {
+                    let (device, sc_desc) = (&self.device, &self.sc_desc);
+                    let layout = &self.texture_bind_group_layout;
+                    let pipelines = catch_panic(Path::new(LAYER_FRAG_PATH), || {
+                        let vs_module = device
+                            .create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Owned(vs)));
+                        let fs_module = device
+                            .create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Owned(fs)));
+                        create_pipelines(device, sc_desc, &vs_module, &fs_module, layout)
+                    });
+                    match pipelines {
+                        Ok(pipelines) => self.pipelines = pipelines,
+                        Err(e) => errors.push(e),
+                    }
+                }
+                (vs, fs) => errors.extend(vs.err().into_iter().chain(fs.err())),
+            }
+        }
+
+        let post_changed = self
+            .post
+            .effects()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, effect)| Some((i, effect.source_path()?)))
+            .filter(|(_, path)| is_changed(path))
+            .collect::<Vec<_>>();
+
+        for (index, path) in post_changed {
+            let (post, device) = (&mut self.post, &self.device);
+            let result = shaders.compile(Path::new(path)).and_then(|spirv| {
+                catch_panic(Path::new(path), || post.set_shader(device, index, spirv))
+            });
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+
+        errors
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Render target -
+//     Either a window's swap chain or, when running headless, a texture
+//     that can be copied back to the cpu.
+// -----------------------------------------------------------------------------
+enum RenderTarget {
+    Window {
+        surface: wgpu::Surface,
+        /// `None` until the first frame
+        swap_chain: Option<wgpu::SwapChain>,
+    },
+    Offscreen {
+        texture: wgpu::Texture,
+        view: wgpu::TextureView,
+    },
+    /// An offscreen target with no size, textures can't be empty
+    Empty,
+}
+
+impl RenderTarget {
+    fn offscreen(device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) -> Self {
+        if sc_desc.width == 0 || sc_desc.height == 0 {
+            return RenderTarget::Empty;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen target"),
+            size: wgpu::Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: sc_desc.format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        RenderTarget::Offscreen { texture, view }
+    }
+}
+
+/// Render targets are bgra, pixels are rgba
+fn bgra_to_pixel(bgra: &[u8]) -> Pixel {
+    Pixel::new(bgra[2], bgra[1], bgra[0], bgra[3])
+}
+
+// -----------------------------------------------------------------------------
+//     - Clear color -
+//     The swap chain is sRGB, so the clear color has to be linear to come
+//     out as the same colour as a canvas pixel. Always opaque, see
+//     `gpu_blend`.
+// -----------------------------------------------------------------------------
+fn clear_color(color: Pixel) -> wgpu::Color {
+    wgpu::Color {
+        r: srgb_to_linear(color.r),
+        g: srgb_to_linear(color.g),
+        b: srgb_to_linear(color.b),
+        a: 1.0,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Create bind group -
+// -----------------------------------------------------------------------------
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &wgpu::Texture,
+    sampler: &wgpu::Sampler,
+    uniforms: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(uniforms.slice(..)),
+            },
+        ],
+        label: Some("meh"),
+    })
 }
 
 // -----------------------------------------------------------------------------
 //     - Create pipeline -
 // -----------------------------------------------------------------------------
+/// One for each blend mode
+fn create_pipelines(
+    device: &wgpu::Device,
+    sc_desc: &wgpu::SwapChainDescriptor,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    texture_bind_group: &wgpu::BindGroupLayout,
+) -> HashMap<BlendMode, wgpu::RenderPipeline> {
+    BlendMode::ALL
+        .iter()
+        .map(|&mode| {
+            let pipeline = create_pipeline(
+                device,
+                sc_desc,
+                vs_module,
+                fs_module,
+                texture_bind_group,
+                mode,
+            );
+            (mode, pipeline)
+        })
+        .collect()
+}
+
 fn create_pipeline(
     device: &wgpu::Device,
     sc_desc: &wgpu::SwapChainDescriptor,
-    vs_module: wgpu::ShaderModule,
-    fs_module: wgpu::ShaderModule,
-    texture_bind_group: wgpu::BindGroupLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    texture_bind_group: &wgpu::BindGroupLayout,
+    blend_mode: BlendMode,
 ) -> wgpu::RenderPipeline {
     let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Render pipeline layout what does this even mean"),
-        bind_group_layouts: &[&texture_bind_group],
+        bind_group_layouts: &[texture_bind_group],
         push_constant_ranges: &[],
     });
 
+    let (color_blend, alpha_blend) = gpu_blend(blend_mode);
+
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Pipeline omg pipeline (render okay)"),
         layout: Some(&render_pipeline_layout),
         vertex_stage: wgpu::ProgrammableStageDescriptor {
-            module: &vs_module,
+            module: vs_module,
             entry_point: "main",
         },
         fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-            module: &fs_module,
+            module: fs_module,
             entry_point: "main",
         }),
         rasterization_state: Some(wgpu::RasterizationStateDescriptor {
@@ -511,8 +1960,8 @@ fn create_pipeline(
         }),
         color_states: &[wgpu::ColorStateDescriptor {
             format: sc_desc.format,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend,
+            alpha_blend,
             write_mask: wgpu::ColorWrite::ALL,
         }],
         primitive_topology: wgpu::PrimitiveTopology::TriangleList,
@@ -528,3 +1977,649 @@ fn create_pipeline(
 
     render_pipeline
 }
+
+// -----------------------------------------------------------------------------
+//     - Gpu blend -
+//     The fragment shader outputs premultiplied alpha. The target is kept
+//     opaque: it's cleared to alpha 1 and no mode writes alpha, so layers
+//     are composited over black. With an opaque destination the terms
+//     fixed function blending can't express (like Multiply's
+//     `src * (1 - dst alpha)`) are zero, and every mode matches
+//     `blend::blend`. Darken and Lighten use min / max, which ignore the
+//     blend factors, so they are only exact for opaque layers.
+// -----------------------------------------------------------------------------
+fn gpu_blend(mode: BlendMode) -> (wgpu::BlendDescriptor, wgpu::BlendDescriptor) {
+    use wgpu::{BlendDescriptor, BlendFactor, BlendOperation};
+
+    let blend = |src_factor, dst_factor, operation| BlendDescriptor {
+        src_factor,
+        dst_factor,
+        operation,
+    };
+
+    let over = blend(
+        BlendFactor::One,
+        BlendFactor::OneMinusSrcAlpha,
+        BlendOperation::Add,
+    );
+    let keep_alpha = blend(BlendFactor::Zero, BlendFactor::One, BlendOperation::Add);
+
+    let color = match mode {
+        BlendMode::Replace => BlendDescriptor::REPLACE,
+        BlendMode::SourceOver => over,
+        BlendMode::Additive => blend(BlendFactor::One, BlendFactor::One, BlendOperation::Add),
+        BlendMode::Multiply => blend(
+            BlendFactor::DstColor,
+            BlendFactor::OneMinusSrcAlpha,
+            BlendOperation::Add,
+        ),
+        BlendMode::Screen => blend(
+            BlendFactor::One,
+            BlendFactor::OneMinusSrcColor,
+            BlendOperation::Add,
+        ),
+        BlendMode::Subtract => blend(
+            BlendFactor::One,
+            BlendFactor::One,
+            BlendOperation::ReverseSubtract,
+        ),
+        BlendMode::Darken => blend(BlendFactor::One, BlendFactor::One, BlendOperation::Min),
+        BlendMode::Lighten => blend(BlendFactor::One, BlendFactor::One, BlendOperation::Max),
+    };
+
+    (color, keep_alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blend::premultiply;
+    use crate::compute::Workgroups;
+    use crate::render::test_util::{rgba, TempPath};
+
+    /// What the gpu does with `desc` for channel `c`, colours in 0..1
+    fn apply(desc: &wgpu::BlendDescriptor, src: [f64; 4], dst: [f64; 4], c: usize) -> f64 {
+        use wgpu::{BlendFactor, BlendOperation};
+
+        let factor = |f| match f {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::SrcColor => src[c],
+            BlendFactor::OneMinusSrcColor => 1.0 - src[c],
+            BlendFactor::SrcAlpha => src[3],
+            BlendFactor::OneMinusSrcAlpha => 1.0 - src[3],
+            BlendFactor::DstColor => dst[c],
+            BlendFactor::OneMinusDstColor => 1.0 - dst[c],
+            BlendFactor::DstAlpha => dst[3],
+            BlendFactor::OneMinusDstAlpha => 1.0 - dst[3],
+            f => panic!("{:?} isn't handled", f),
+        };
+
+        let (s, d) = (
+            src[c] * factor(desc.src_factor),
+            dst[c] * factor(desc.dst_factor),
+        );
+        let out = match desc.operation {
+            BlendOperation::Add => s + d,
+            BlendOperation::Subtract => s - d,
+            BlendOperation::ReverseSubtract => d - s,
+            BlendOperation::Min => src[c].min(dst[c]),
+            BlendOperation::Max => src[c].max(dst[c]),
+        };
+        out.clamp(0.0, 1.0)
+    }
+
+    #[test]
+    fn gpu_blend_matches_cpu_blend() {
+        let sources = [
+            Pixel::new(255, 0, 0, 255),
+            Pixel::new(40, 200, 120, 255),
+            Pixel::new(255, 128, 0, 128),
+            Pixel::new(10, 20, 30, 0),
+        ];
+        // The target is always opaque
+        let destinations = [
+            Pixel::new(0, 0, 0, 255),
+            Pixel::new(255, 255, 255, 255),
+            Pixel::new(100, 150, 200, 255),
+        ];
+
+        for &mode in &BlendMode::ALL {
+            let (color, alpha) = gpu_blend(mode);
+
+            for &src in &sources {
+                // Replace drops the alpha on the gpu, and min / max ignore
+                // it, so these are only the same for opaque sources
+                let exact_for_translucent = !matches!(
+                    mode,
+                    BlendMode::Replace | BlendMode::Darken | BlendMode::Lighten
+                );
+                if src.a != 255 && !exact_for_translucent {
+                    continue;
+                }
+
+                for &dst in &destinations {
+                    let src = premultiply(src);
+                    let cpu = blend(src, dst, mode, AlphaMode::Premultiplied);
+
+                    let unorm = |p: Pixel| [p.r, p.g, p.b, p.a].map(|c| c as f64 / 255.0);
+                    let (s, d) = (unorm(src), unorm(dst));
+                    let gpu = [
+                        apply(&color, s, d, 0),
+                        apply(&color, s, d, 1),
+                        apply(&color, s, d, 2),
+                        apply(&alpha, s, d, 3),
+                    ];
+
+                    for (c, (cpu, gpu)) in unorm(cpu).iter().zip(&gpu).enumerate() {
+                        assert!(
+                            (cpu - gpu).abs() <= 1.0 / 255.0,
+                            "{:?} {:?} on {:?}, channel {}: cpu {} gpu {}",
+                            mode,
+                            src,
+                            dst,
+                            c,
+                            cpu,
+                            gpu
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multiply_over_black_is_black() {
+        let (color, _) = gpu_blend(BlendMode::Multiply);
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let black = [0.0, 0.0, 0.0, 1.0];
+        assert_eq!(apply(&color, red, black, 0), 0.0);
+
+        let cpu = blend(
+            Pixel::new(255, 0, 0, 255),
+            Pixel::black(),
+            BlendMode::Multiply,
+            AlphaMode::Straight,
+        );
+        assert_eq!((cpu.r, cpu.g, cpu.b, cpu.a), (0, 0, 0, 255));
+    }
+
+    #[test]
+    fn one_pixel_uploads_one_texel() {
+        let mut renderer = Renderer::new_software(64, 32, 64, 32).unwrap();
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 0);
+
+        renderer.pixels().set(10, 20, Pixel::new(255, 0, 0, 255));
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 4);
+
+        renderer.pixels().mark_all_dirty();
+        renderer.draw().unwrap();
+        assert_eq!(renderer.upload_bytes(), 64 * 32 * 4);
+    }
+
+    #[test]
+    fn dirty_rects_merge_to_the_limit() {
+        let mut pixels = PixelBuffer::new(64, 64);
+        pixels.take_dirty();
+
+        // Far enough apart that none of them touch
+        let points = (0..20)
+            .map(|i| ((i % 5) * 12, (i / 5) * 12))
+            .collect::<Vec<_>>();
+        for &(x, y) in &points {
+            pixels.set(x, y, Pixel::new(255, 255, 255, 255));
+        }
+
+        let dirty = pixels.dirty_rects();
+        assert!(dirty.len() <= MAX_DIRTY_RECTS, "{} rects", dirty.len());
+        for &(x, y) in &points {
+            let covered = dirty.iter().any(|r| r.contains(x as i32, y as i32));
+            assert!(covered, "({}, {}) isn't in {:?}", x, y, dirty);
+        }
+        for r in dirty {
+            assert!(pixels.bounds().contains_rect(r));
+        }
+    }
+
+    #[test]
+    fn dirty_rects_that_touch_are_merged() {
+        let mut pixels = PixelBuffer::new(16, 16);
+        pixels.take_dirty();
+
+        pixels.mark_dirty(Rect::new(0, 0, 4, 4));
+        pixels.mark_dirty(Rect::new(8, 0, 4, 4));
+        pixels.mark_dirty(Rect::new(4, 0, 4, 4));
+        assert_eq!(pixels.dirty_rects(), [Rect::new(0, 0, 12, 4)]);
+
+        // Already covered
+        pixels.mark_dirty(Rect::new(1, 1, 2, 2));
+        assert_eq!(pixels.dirty_rects(), [Rect::new(0, 0, 12, 4)]);
+
+        // Clipped to the buffer
+        pixels.take_dirty();
+        pixels.mark_dirty(Rect::new(-4, 14, 8, 8));
+        assert_eq!(pixels.dirty_rects(), [Rect::new(0, 14, 4, 2)]);
+    }
+
+    #[test]
+    fn uploads_are_checked() {
+        let pixels = PixelBuffer::new(4, 2);
+        assert!(check_upload((4, 2), &pixels, Rect::new(0, 0, 4, 2)).is_ok());
+        assert!(check_upload((4, 2), &pixels, Rect::new(3, 1, 1, 1)).is_ok());
+
+        match check_upload((2, 4), &pixels, Rect::new(0, 0, 1, 1)) {
+            Err(RendererError::UploadSizeMismatch { expected, actual }) => {
+                assert_eq!((expected, actual), ((2, 4), (4, 2)))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Same size, but the rect sticks out
+        for &rect in &[
+            Rect::new(3, 0, 2, 1),
+            Rect::new(-1, 0, 1, 1),
+            Rect::new(0, 0, 4, 3),
+        ] {
+            match check_upload((4, 2), &pixels, rect) {
+                Err(RendererError::UploadOutOfBounds { rect: r, size }) => {
+                    assert_eq!((r, size), (rect, (4, 2)))
+                }
+                other => panic!("{:?}: {:?}", rect, other),
+            }
+        }
+    }
+
+    #[test]
+    fn zero_output_size_is_rejected() {
+        for &(w, h) in &[(0, 16), (16, 0), (MAX_TEXTURE_SIZE + 1, 16)] {
+            match Renderer::new_headless(16, 16, w, h) {
+                Err(RendererError::InvalidOutputSize { width, height }) => {
+                    assert_eq!((width, height), (w, h))
+                }
+                other => panic!("{}x{}: {:?}", w, h, other.map(|_| ()).err()),
+            }
+            match Renderer::new_software(16, 16, w, h) {
+                Err(RendererError::InvalidOutputSize { .. }) => {}
+                other => panic!("{}x{}: {:?}", w, h, other.map(|_| ()).err()),
+            }
+        }
+    }
+
+    // The tests that need a gpu are ignored, run them with
+    // `cargo test -- --ignored` where there is one
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn headless_renders_the_canvas() {
+        let mut renderer = Renderer::new_headless(4, 2, 4, 2).unwrap();
+
+        for (x, y, p) in renderer.pixels().enumerate_mut() {
+            *p = Pixel::new(x as u8 * 60, y as u8 * 200, 90, 255);
+        }
+        renderer.draw().unwrap();
+        renderer.render().unwrap();
+
+        let frame = renderer.read_frame().unwrap();
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+        for (x, y, p) in frame.enumerate() {
+            let expected = (x as u8 * 60, y as u8 * 200, 90, 255);
+            assert_eq!(rgba(p), expected, "at ({}, {})", x, y);
+        }
+    }
+
+    /// Fill a renderer with the same layers in every blend mode, some
+    /// hanging off the canvas, some see-through, one hidden
+    fn build_layer_stack(renderer: &mut Renderer) {
+        for (x, y, p) in renderer.pixels().enumerate_mut() {
+            *p = Pixel::new(x as u8 * 30, y as u8 * 60, 200 - x as u8 * 20, 255);
+        }
+
+        let layers = renderer.layers_mut();
+        for (i, &mode) in BlendMode::ALL.iter().enumerate() {
+            let id = layers.add(3, 3);
+            let layer = layers.get_mut(id).unwrap();
+            layer.blend_mode = mode;
+            layer.x = i as i32 - 1;
+            layer.y = i as i32 % 3 - 1;
+            layer.opacity = if i % 2 == 0 { 1.0 } else { 0.6 };
+            for (x, y, p) in layer.pixels.enumerate_mut() {
+                let v = (i as u8 + 1) * 25;
+                *p = Pixel::new(v, 255 - v, x as u8 * 100, 128 + y as u8 * 60);
+            }
+        }
+
+        let hidden = layers.add(8, 4);
+        let layer = layers.get_mut(hidden).unwrap();
+        layer.pixels.fill(Pixel::new(255, 0, 255, 255));
+        layer.visible = false;
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn headless_matches_software() {
+        let mut headless = Renderer::new_headless(8, 4, 8, 4).unwrap();
+        let mut software = Renderer::new_software(8, 4, 8, 4).unwrap();
+
+        let mut frames = Vec::new();
+        for renderer in [&mut headless, &mut software].iter_mut() {
+            build_layer_stack(renderer);
+            renderer.draw().unwrap();
+            renderer.render().unwrap();
+            frames.push(renderer.read_frame().unwrap());
+        }
+
+        // The backends round differently, but only by a little
+        const TOLERANCE: i16 = 3;
+        for ((x, y, gpu), (_, _, cpu)) in frames[0].enumerate().zip(frames[1].enumerate()) {
+            let gpu_channels = [gpu.r, gpu.g, gpu.b, gpu.a];
+            let cpu_channels = [cpu.r, cpu.g, cpu.b, cpu.a];
+            for (g, c) in gpu_channels.iter().zip(&cpu_channels) {
+                assert!(
+                    (*g as i16 - *c as i16).abs() <= TOLERANCE,
+                    "at ({}, {}): gpu {:?}, software {:?}",
+                    x,
+                    y,
+                    gpu_channels,
+                    cpu_channels
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn bad_shaders_are_errors() {
+        let mut renderer = Renderer::new_headless(4, 2, 4, 2).unwrap();
+
+        // Loaded at runtime rather than included
+        let not_spirv = b"void main() {}\n".to_vec();
+        assert!(renderer.set_shadertoy(Some(&not_spirv)).is_err());
+        assert!(renderer.set_shadertoy(Some(&not_spirv[..15])).is_err());
+
+        let effects = [PostEffect::Custom(not_spirv.clone())];
+        assert!(renderer.set_post_effects(&effects).is_err());
+        assert!(renderer.post_effects().is_empty());
+
+        let shader = ComputeShader {
+            spirv: &not_spirv,
+            workgroups: Workgroups::PerPixel(8, 8),
+            uniforms: Vec::new(),
+            buffers: Vec::new(),
+        };
+        assert!(renderer.add_compute(&shader).is_err());
+        renderer.render().unwrap();
+    }
+
+    const FORMATS: [CanvasFormat; 4] = [
+        CanvasFormat::Rgba8Srgb,
+        CanvasFormat::Rgba8Linear,
+        CanvasFormat::Bgra8Srgb,
+        CanvasFormat::Rgba16Float,
+    ];
+
+    /// Read back a canvas in `format` after drawing it, it has to come back
+    /// as it was drawn
+    fn check_read_canvas(mut renderer: Renderer, format: CanvasFormat) {
+        renderer.pixels().set_alpha_mode(AlphaMode::Premultiplied);
+        for (x, y, p) in renderer.pixels().enumerate_mut() {
+            let v = (x * 16 + y) as u8;
+            *p = Pixel::new(v, v / 2, 255 - v, v | 0x80);
+        }
+        renderer.draw().unwrap();
+        renderer.render().unwrap();
+
+        let canvas = renderer.read_canvas().unwrap();
+        assert_eq!(
+            canvas.alpha_mode(),
+            AlphaMode::Premultiplied,
+            "{:?}",
+            format
+        );
+        for ((x, y, actual), (_, _, expected)) in
+            canvas.enumerate().zip(renderer.pixels().enumerate())
+        {
+            assert_eq!(
+                rgba(actual),
+                rgba(expected),
+                "{:?} at ({}, {})",
+                format,
+                x,
+                y
+            );
+        }
+    }
+
+    fn format_config(format: CanvasFormat) -> RendererConfig {
+        RendererConfig {
+            canvas_format: format,
+            ..RendererConfig::default()
+        }
+    }
+
+    #[test]
+    fn only_draw_records_frames() {
+        let path = TempPath::new("set-filter.y4m");
+        let mut renderer = Renderer::new_software(2, 2, 2, 2).unwrap();
+        renderer
+            .start_recording(RecordOutput::Y4m(path.0.clone()))
+            .unwrap();
+
+        renderer.draw().unwrap();
+        renderer.set_filter(FilterMode::Bilinear).unwrap();
+        // A frame of the new size would stop the recording
+        renderer.resize_canvas(4, 4).unwrap();
+        renderer.resize_canvas(2, 2).unwrap();
+        renderer.draw().unwrap();
+        assert!(renderer.is_recording());
+        renderer.stop_recording().unwrap();
+
+        let bytes = std::fs::read(&path.0).unwrap();
+        let frames = bytes.windows(6).filter(|w| w == b"FRAME\n").count();
+        assert_eq!(frames, 2);
+        assert!(renderer.take_recording_error().is_none());
+    }
+
+    #[test]
+    fn read_canvas_round_trips() {
+        for format in FORMATS.iter().copied() {
+            let config = format_config(format);
+            let renderer = Renderer::software_with_config(16, 2, 16, 2, config).unwrap();
+            check_read_canvas(renderer, format);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn headless_read_canvas_round_trips() {
+        for format in FORMATS.iter().copied() {
+            let config = format_config(format);
+            let renderer = Renderer::headless_with_config(16, 2, 16, 2, config).unwrap();
+            check_read_canvas(renderer, format);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn capture_needs_a_size() {
+        let mut renderer = Renderer::new_headless(4, 2, 4, 2).unwrap();
+
+        renderer.set_post_effects(&[PostEffect::Scanlines]).unwrap();
+        renderer.render().unwrap();
+        let first = renderer.capture_frame().unwrap();
+        let second = renderer.capture_frame().unwrap();
+        assert_eq!(&first[..], &second[..]);
+
+        renderer.resize(PhysicalSize::new(0, 0));
+        assert!(renderer.capture_frame().is_err());
+    }
+
+    /// Render a row of white (true) and black pixels with `filter` into an
+    /// output `output_w` wide, and return the red channel of the frame
+    fn filtered_row(filter: FilterMode, canvas: &[bool], output_w: u32) -> Vec<u8> {
+        let config = RendererConfig {
+            filter,
+            ..RendererConfig::default()
+        };
+        let w = canvas.len() as u32;
+        let mut renderer = Renderer::headless_with_config(w, 1, output_w, 1, config).unwrap();
+        for (x, _, p) in renderer.pixels().enumerate_mut() {
+            let v = if canvas[x as usize] { 255 } else { 0 };
+            *p = Pixel::new(v, v, v, 255);
+        }
+        renderer.draw().unwrap();
+        renderer.render().unwrap();
+
+        let frame = renderer.read_frame().unwrap();
+        frame.row(0).unwrap().iter().map(|p| p.r).collect()
+    }
+
+    fn assert_near(actual: &[u8], expected: &[u8], what: &str) {
+        let near = actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (*a as i16 - *e as i16).abs() <= 2);
+        assert!(near, "{}: got {:?}, expected {:?}", what, actual, expected);
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn trilinear_samples_the_mips() {
+        // White outer columns, black middle ones. Bilinear at 1x1 samples
+        // the middle, the 1x1 mip level is the average of all of them: half
+        // way in linear light, which is 188 in sRGB.
+        let canvas = [true, false, false, true];
+        assert_near(
+            &filtered_row(FilterMode::Trilinear, &canvas, 1),
+            &[188],
+            "trilinear",
+        );
+        assert_near(
+            &filtered_row(FilterMode::Bilinear, &canvas, 1),
+            &[0],
+            "bilinear",
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn sharp_bilinear_only_blends_the_seams() {
+        // 2.5 window pixels per texel, the middle one is on the seam.
+        // Bilinear would blend the ones next to it as well.
+        let canvas = [false, true];
+        assert_near(
+            &filtered_row(FilterMode::SharpBilinear, &canvas, 5),
+            &[0, 0, 188, 255, 255],
+            "sharp bilinear",
+        );
+    }
+
+    /// 3x2 buffer where the red channel is the flat index
+    fn numbered() -> PixelBuffer {
+        let mut pixels = PixelBuffer::new(3, 2);
+        for (i, p) in pixels.enumerate_mut().map(|(_, _, p)| p).enumerate() {
+            *p = Pixel::new(i as u8, 0, 0, 255);
+        }
+        pixels
+    }
+
+    #[test]
+    fn get_and_set_stay_in_bounds() {
+        let mut pixels = numbered();
+        assert_eq!(pixels.get(2, 1).map(|p| p.r), Some(5));
+        assert!(pixels.get(3, 0).is_none());
+        assert!(pixels.get(0, 2).is_none());
+        assert!(pixels.get(u32::MAX, u32::MAX).is_none());
+        assert!(pixels.get_mut(3, 1).is_none());
+        assert_eq!(pixels.index(1, 1), Some(4));
+        assert_eq!(pixels.index(3, 0), None);
+
+        pixels.take_dirty();
+        pixels.set(3, 0, Pixel::new(99, 0, 0, 255));
+        pixels.set(0, u32::MAX, Pixel::new(99, 0, 0, 255));
+        assert!(pixels.enumerate().all(|(_, _, p)| p.r != 99));
+        assert!(!pixels.is_dirty());
+
+        pixels.set(1, 0, Pixel::new(99, 0, 0, 255));
+        assert_eq!(pixels.get(1, 0).map(|p| p.r), Some(99));
+        assert_eq!(pixels.dirty_rects(), [Rect::new(1, 0, 1, 1)]);
+    }
+
+    #[test]
+    fn enumerate_goes_row_by_row() {
+        let pixels = numbered();
+        let order = pixels
+            .enumerate()
+            .map(|(x, y, p)| (x, y, p.r))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                (0, 0, 0),
+                (1, 0, 1),
+                (2, 0, 2),
+                (0, 1, 3),
+                (1, 1, 4),
+                (2, 1, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_are_width_long() {
+        let pixels = numbered();
+        let red = |row: &[Pixel]| row.iter().map(|p| p.r).collect::<Vec<_>>();
+        assert_eq!(pixels.row(1).map(red), Some(vec![3, 4, 5]));
+        assert!(pixels.row(2).is_none());
+        assert_eq!(
+            pixels.rows().map(red).collect::<Vec<_>>(),
+            [[0, 1, 2], [3, 4, 5]]
+        );
+    }
+
+    #[test]
+    fn growing_keeps_the_top_left() {
+        let mut pixels = numbered();
+        pixels.set_alpha_mode(AlphaMode::Premultiplied);
+        let grown = pixels.resized(4, 3);
+        assert_eq!((grown.width(), grown.height()), (4, 3));
+        assert_eq!(grown.alpha_mode(), AlphaMode::Premultiplied);
+
+        for (x, y, p) in grown.enumerate() {
+            let expected = match pixels.get(x, y) {
+                Some(old) => (old.r, old.a),
+                None => (0, 255),
+            };
+            assert_eq!((p.r, p.a), expected, "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn shrinking_crops() {
+        let shrunk = numbered().resized(2, 1);
+        let red = shrunk.enumerate().map(|(_, _, p)| p.r).collect::<Vec<_>>();
+        assert_eq!(red, [0, 1]);
+
+        let empty = numbered().resized(0, 0);
+        assert_eq!(empty.enumerate().count(), 0);
+    }
+
+    #[test]
+    fn resize_canvas_keeps_the_pixels() {
+        let mut renderer = Renderer::new_software(3, 2, 3, 2).unwrap();
+        *renderer.pixels() = numbered();
+        renderer.resize_canvas(2, 4).unwrap();
+
+        assert_eq!(renderer.layers().size(), (2, 4));
+        let red = renderer
+            .pixels()
+            .enumerate()
+            .map(|(_, _, p)| p.r)
+            .collect::<Vec<_>>();
+        assert_eq!(red, [0, 1, 3, 4, 0, 0, 0, 0]);
+
+        assert!(renderer.resize_canvas(0, 4).is_err());
+        assert_eq!(renderer.layers().size(), (2, 4));
+    }
+}
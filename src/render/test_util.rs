@@ -0,0 +1,82 @@
+use std::fs;
+use std::path::PathBuf;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+use crate::render::{Pixel, PixelBuffer};
+
+// -----------------------------------------------------------------------------
+//     - Test util -
+//     Fixtures the tests of every module share
+// -----------------------------------------------------------------------------
+pub type Rgba = (u8, u8, u8, u8);
+
+pub fn pixel((r, g, b, a): Rgba) -> Pixel {
+    Pixel { r, g, b, a }
+}
+
+/// A pixel as a tuple, which compares and prints shorter
+pub fn rgba(p: &Pixel) -> Rgba {
+    (p.r, p.g, p.b, p.a)
+}
+
+pub fn rgba_at(pixels: &PixelBuffer, x: u32, y: u32) -> Rgba {
+    rgba(pixels.get(x, y).expect("inside the buffer"))
+}
+
+pub fn device() -> DeviceId {
+    // Only compared, never passed back to winit
+    unsafe { DeviceId::dummy() }
+}
+
+#[allow(deprecated)]
+pub fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+    WindowEvent::KeyboardInput {
+        device_id: device(),
+        input: KeyboardInput {
+            scancode: 0,
+            state,
+            virtual_keycode: Some(key),
+            modifiers: ModifiersState::empty(),
+        },
+        is_synthetic: false,
+    }
+}
+
+#[allow(deprecated)]
+pub fn button(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+    WindowEvent::MouseInput {
+        device_id: device(),
+        state,
+        button,
+        modifiers: ModifiersState::empty(),
+    }
+}
+
+#[allow(deprecated)]
+pub fn cursor_moved(x: f64, y: f64) -> WindowEvent<'static> {
+    WindowEvent::CursorMoved {
+        device_id: device(),
+        position: PhysicalPosition::new(x, y),
+        modifiers: ModifiersState::empty(),
+    }
+}
+
+/// A path in the temp dir that's removed when dropped
+pub struct TempPath(pub PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let name = format!("wgpuhelloworld-{}-{}", std::process::id(), name);
+        Self(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,304 @@
+use crate::layer::CanvasLayer;
+
+// -----------------------------------------------------------------------------
+//     - Scaling mode -
+//     How the canvas is fitted into the window
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScalingMode {
+    /// Cover the whole window, pixels are not square unless the window
+    /// happens to have the same aspect ratio as the canvas
+    #[default]
+    Stretch,
+    /// As large as possible while keeping the aspect ratio, the rest of the
+    /// window is filled with the letterbox colour
+    AspectFit,
+    /// Largest whole number scale that fits, centred. Never smaller than
+    /// 1:1, so a window smaller than the canvas crops it.
+    Integer,
+    /// Cover the whole window while keeping the aspect ratio, cropping
+    /// whatever sticks out
+    FillCrop,
+}
+
+impl ScalingMode {
+    /// Where the canvas ends up in a window of the given size
+    pub fn viewport(self, canvas_size: (u32, u32), window_size: (u32, u32)) -> Viewport {
+        let (cw, ch) = (canvas_size.0 as f32, canvas_size.1 as f32);
+        let (ww, wh) = (window_size.0 as f32, window_size.1 as f32);
+
+        if cw == 0.0 || ch == 0.0 || ww == 0.0 || wh == 0.0 {
+            return Viewport::default();
+        }
+
+        let (sx, sy) = (ww / cw, wh / ch);
+        let scale = match self {
+            ScalingMode::Stretch => {
+                return Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: ww,
+                    height: wh,
+                }
+            }
+            ScalingMode::AspectFit => sx.min(sy),
+            ScalingMode::Integer => sx.min(sy).floor().max(1.0),
+            ScalingMode::FillCrop => sx.max(sy),
+        };
+
+        let (width, height) = (cw * scale, ch * scale);
+        let (mut x, mut y) = ((ww - width) / 2.0, (wh - height) / 2.0);
+
+        // Keep canvas pixels on window pixel boundaries
+        if self == ScalingMode::Integer {
+            x = x.floor();
+            y = y.floor();
+        }
+
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Parse a scaling mode name, e.g. for an environment variable. Case
+/// doesn't matter.
+pub fn parse_scaling_mode(s: &str) -> Option<ScalingMode> {
+    match s.trim().to_lowercase().as_str() {
+        "stretch" => Some(ScalingMode::Stretch),
+        "fit" | "aspect-fit" | "aspect_fit" => Some(ScalingMode::AspectFit),
+        "integer" => Some(ScalingMode::Integer),
+        "fill" | "crop" | "fill-crop" | "fill_crop" => Some(ScalingMode::FillCrop),
+        _ => None,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Viewport -
+//     The area covered by the canvas, in physical window pixels. Can be
+//     larger than the window, or start at a negative position, when the
+//     canvas is cropped.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+
+    /// A position on the canvas in window pixels, the inverse of
+    /// `to_canvas`.
+    pub fn to_window(self, x: f32, y: f32, canvas_size: (u32, u32)) -> (f32, f32) {
+        let scale_x = self.width / canvas_size.0.max(1) as f32;
+        let scale_y = self.height / canvas_size.1.max(1) as f32;
+        (self.x + x * scale_x, self.y + y * scale_y)
+    }
+
+    /// Left, top, right and bottom of a layer in window pixels
+    pub fn layer_rect(&self, layer: &CanvasLayer, canvas_size: (u32, u32)) -> [f32; 4] {
+        let (left, top) = self.to_window(layer.x as f32, layer.y as f32, canvas_size);
+        // In i64 so a layer near i32::MAX doesn't overflow
+        let (right, bottom) = self.to_window(
+            (layer.x as i64 + layer.pixels.width() as i64) as f32,
+            (layer.y as i64 + layer.pixels.height() as i64) as f32,
+            canvas_size,
+        );
+        [left, top, right, bottom]
+    }
+
+    /// The canvas pixel at a window position, `None` if the position is
+    /// outside the canvas.
+    pub fn to_canvas(self, x: f64, y: f64, canvas_size: (u32, u32)) -> Option<(u32, u32)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let cx = (x - self.x as f64) * canvas_size.0 as f64 / self.width as f64;
+        let cy = (y - self.y as f64) * canvas_size.1 as f64 / self.height as f64;
+
+        if cx < 0.0 || cy < 0.0 || cx >= canvas_size.0 as f64 || cy >= canvas_size.1 as f64 {
+            return None;
+        }
+
+        Some((cx as u32, cy as u32))
+    }
+
+    /// The part of the viewport inside the window as whole pixels,
+    /// (x, y, width, height). `None` if nothing is visible.
+    pub fn visible(&self, window_size: (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+        let left = self.x.round().max(0.0);
+        let top = self.y.round().max(0.0);
+        let right = (self.x + self.width).round().min(window_size.0 as f32);
+        let bottom = (self.y + self.height).round().min(window_size.1 as f32);
+
+        if right <= left || bottom <= top {
+            return None;
+        }
+
+        Some((
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::LayerStack;
+
+    const CANVAS: (u32, u32) = (4, 3);
+    // Not a multiple of the canvas in either direction
+    const WINDOW: (u32, u32) = (10, 7);
+
+    fn assert_viewport(actual: Viewport, expected: [f32; 4]) {
+        let actual_array = [actual.x, actual.y, actual.width, actual.height];
+        for (a, e) in actual_array.iter().zip(&expected) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn stretch_covers_the_window() {
+        let viewport = ScalingMode::Stretch.viewport(CANVAS, WINDOW);
+        assert_viewport(viewport, [0.0, 0.0, 10.0, 7.0]);
+    }
+
+    #[test]
+    fn aspect_fit_letterboxes() {
+        // Height limits the scale to 7/3, leaving bars left and right
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, WINDOW);
+        assert_viewport(viewport, [1.0 / 3.0, 0.0, 28.0 / 3.0, 7.0]);
+
+        // And bars above and below in a wide window
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, (20, 7));
+        assert_viewport(viewport, [10.0 - 14.0 / 3.0, 0.0, 28.0 / 3.0, 7.0]);
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, (10, 20));
+        assert_viewport(viewport, [0.0, 6.25, 10.0, 7.5]);
+    }
+
+    #[test]
+    fn integer_scale_is_whole_and_on_pixel_boundaries() {
+        // 2.33 rounds down to 2, the half pixel offset rounds down too
+        let viewport = ScalingMode::Integer.viewport(CANVAS, WINDOW);
+        assert_viewport(viewport, [1.0, 0.0, 8.0, 6.0]);
+
+        // Never below 1:1, a smaller window crops
+        let viewport = ScalingMode::Integer.viewport(CANVAS, (3, 2));
+        assert_viewport(viewport, [-1.0, -1.0, 4.0, 3.0]);
+    }
+
+    #[test]
+    fn fill_crop_covers_and_crops() {
+        // Width sets the scale to 2.5, the extra half pixel is split
+        // between top and bottom
+        let viewport = ScalingMode::FillCrop.viewport(CANVAS, WINDOW);
+        assert_viewport(viewport, [0.0, -0.25, 10.0, 7.5]);
+    }
+
+    #[test]
+    fn zero_sizes_give_an_empty_viewport() {
+        let modes = [
+            ScalingMode::Stretch,
+            ScalingMode::AspectFit,
+            ScalingMode::Integer,
+            ScalingMode::FillCrop,
+        ];
+        for &mode in &modes {
+            for &(canvas, window) in &[(CANVAS, (0, 0)), (CANVAS, (10, 0)), ((0, 3), WINDOW)] {
+                let viewport = mode.viewport(canvas, window);
+                assert_eq!(viewport, Viewport::default(), "{:?}", mode);
+                assert!(viewport.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!(parse_scaling_mode(" Fit "), Some(ScalingMode::AspectFit));
+        assert_eq!(parse_scaling_mode("INTEGER"), Some(ScalingMode::Integer));
+        assert_eq!(parse_scaling_mode("fill_crop"), Some(ScalingMode::FillCrop));
+        assert_eq!(parse_scaling_mode("zoom"), None);
+    }
+
+    #[test]
+    fn letterbox_bars_are_outside_the_canvas() {
+        // Bars left and right
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, WINDOW);
+        assert_eq!(viewport.to_canvas(0.2, 3.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(9.8, 3.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(5.0, 3.0, CANVAS), Some((2, 1)));
+
+        // Bars above and below
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, (10, 20));
+        assert_eq!(viewport.to_canvas(5.0, 6.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(5.0, 14.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(5.0, 10.0, CANVAS), Some((2, 1)));
+
+        // Outside the window entirely
+        assert_eq!(viewport.to_canvas(-1.0, 10.0, CANVAS), None);
+        assert_eq!(Viewport::default().to_canvas(0.0, 0.0, CANVAS), None);
+    }
+
+    #[test]
+    fn viewport_edges_map_to_the_first_and_last_pixel() {
+        // (1, 0, 8, 6), two window pixels per canvas pixel
+        let viewport = ScalingMode::Integer.viewport(CANVAS, WINDOW);
+        assert_eq!(viewport.to_canvas(1.0, 0.0, CANVAS), Some((0, 0)));
+        assert_eq!(viewport.to_canvas(8.999, 5.999, CANVAS), Some((3, 2)));
+        assert_eq!(viewport.to_canvas(0.999, 0.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(9.0, 0.0, CANVAS), None);
+        assert_eq!(viewport.to_canvas(1.0, 6.0, CANVAS), None);
+
+        // And back again
+        assert_eq!(viewport.to_window(0.0, 0.0, CANVAS), (1.0, 0.0));
+        assert_eq!(viewport.to_window(4.0, 3.0, CANVAS), (9.0, 6.0));
+    }
+
+    #[test]
+    fn visible_is_clamped_to_the_window() {
+        let viewport = ScalingMode::AspectFit.viewport(CANVAS, (10, 20));
+        assert_eq!(viewport.visible((10, 20)), Some((0, 6, 10, 8)));
+
+        let viewport = ScalingMode::Integer.viewport(CANVAS, WINDOW);
+        assert_eq!(viewport.visible(WINDOW), Some((1, 0, 8, 6)));
+
+        // Cropped on every side
+        let viewport = ScalingMode::Integer.viewport(CANVAS, (3, 2));
+        assert_eq!(viewport.visible((3, 2)), Some((0, 0, 3, 2)));
+        let viewport = ScalingMode::FillCrop.viewport(CANVAS, WINDOW);
+        assert_eq!(viewport.visible(WINDOW), Some((0, 0, 10, 7)));
+
+        let outside = Viewport {
+            x: 20.0,
+            y: 0.0,
+            width: 4.0,
+            height: 3.0,
+        };
+        assert_eq!(outside.visible(WINDOW), None);
+        assert_eq!(Viewport::default().visible(WINDOW), None);
+    }
+
+    #[test]
+    fn layer_rect_near_i32_max_does_not_overflow() {
+        let mut layers = LayerStack::new(2, 2);
+        layers.base_mut().x = i32::MAX;
+        layers.base_mut().y = i32::MAX;
+
+        let viewport = ScalingMode::Stretch.viewport((2, 2), (2, 2));
+        let [left, top, right, bottom] = viewport.layer_rect(layers.base(), (2, 2));
+        assert!(right >= left && right > 0.0);
+        assert!(bottom >= top && bottom > 0.0);
+    }
+}
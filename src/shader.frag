@@ -6,6 +6,37 @@ layout(location=0) out vec4 f_color;
 layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
 
+layout(set = 0, binding = 2) uniform LayerUniforms {
+    vec4 u_rect;
+    float u_opacity;
+    float u_premultiplied;
+    vec2 u_texture_size;
+    float u_sharp_bilinear;
+};
+
+// Nearest sampling in the middle of each texel and a one pixel wide
+// linear blend on the edges between them, which keeps pixels crisp at
+// any scale. Needs a linear sampler.
+vec2 sharp_bilinear(vec2 tex_coords) {
+    vec2 texel = tex_coords * u_texture_size;
+    vec2 seam = floor(texel + 0.5);
+    vec2 texels_per_pixel = fwidth(texel);
+    texel = seam + clamp((texel - seam) / texels_per_pixel, -0.5, 0.5);
+    return texel / u_texture_size;
+}
+
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+    vec2 tex_coords = v_tex_coords;
+    if (u_sharp_bilinear > 0.5) {
+        tex_coords = sharp_bilinear(tex_coords);
+    }
+
+    vec4 color = texture(sampler2D(t_diffuse, s_diffuse), tex_coords);
+
+    // Blending expects premultiplied alpha
+    if (u_premultiplied < 0.5) {
+        color.rgb *= color.a;
+    }
+
+    f_color = color * u_opacity;
 }
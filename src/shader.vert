@@ -5,7 +5,17 @@ layout(location=1) in vec2 a_tex_coords;
 
 layout(location=0) out vec2 v_tex_coords;
 
+layout(set = 0, binding = 2) uniform LayerUniforms {
+    // left, top, right, bottom
+    vec4 u_rect;
+    float u_opacity;
+    float u_premultiplied;
+    vec2 u_texture_size;
+    float u_sharp_bilinear;
+};
+
 void main() {
     v_tex_coords = a_tex_coords;
-    gl_Position = vec4(a_position, 1.0);
+    // The quad is stretched over the layer rect, so only the tex coords matter
+    gl_Position = vec4(mix(u_rect.xy, u_rect.zw, a_tex_coords), 0.0, 1.0);
 }
@@ -0,0 +1,322 @@
+use std::time::Instant;
+
+use crate::error::RendererError;
+use crate::post::check_spirv;
+
+// -----------------------------------------------------------------------------
+//     - Shadertoy mouse -
+//     `iMouse` the way Shadertoy fills it in, in canvas pixels
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ShadertoyMouse {
+    position: (f32, f32),
+    click: (f32, f32),
+    down: bool,
+    /// Pressed this frame
+    clicked: bool,
+}
+
+impl ShadertoyMouse {
+    /// Call once a frame. `position` is the canvas pixel under the cursor,
+    /// if there is one.
+    pub fn update(&mut self, position: Option<(u32, u32)>, pressed: bool) {
+        self.clicked = pressed && !self.down;
+        self.down = pressed;
+
+        if let (true, Some((x, y))) = (pressed, position) {
+            self.position = (x as f32 + 0.5, y as f32 + 0.5);
+            if self.clicked {
+                self.click = self.position;
+            }
+        }
+    }
+
+    pub fn uniform(&self) -> [f32; 4] {
+        let sign = |positive| if positive { 1.0 } else { -1.0 };
+        [
+            self.position.0,
+            self.position.1,
+            self.click.0 * sign(self.down),
+            self.click.1 * sign(self.clicked),
+        ]
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Shadertoy uniforms -
+// -----------------------------------------------------------------------------
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ShadertoyUniforms {
+    resolution: [f32; 3],
+    time: f32,
+    time_delta: f32,
+    frame: i32,
+    _padding: [f32; 2],
+    mouse: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for ShadertoyUniforms {}
+unsafe impl bytemuck::Zeroable for ShadertoyUniforms {}
+
+// -----------------------------------------------------------------------------
+//     - Shadertoy -
+//     Runs a user fragment shader over the whole canvas every frame, with
+//     the base layer as `iChannel0` and its own last frame as `iChannel1`.
+//     What it outputs is drawn in place of the base layer. See
+//     `shadertoy/trails.frag` for the prelude a shader needs.
+// -----------------------------------------------------------------------------
+pub struct Shadertoy {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniforms: wgpu::Buffer,
+    /// The last frame and the one being rendered, swapped every frame.
+    /// Created on the first frame and whenever the canvas is resized.
+    targets: Option<[wgpu::Texture; 2]>,
+    size: (u32, u32),
+    started: Instant,
+    last_frame: Instant,
+    frame: i32,
+    mouse: [f32; 4],
+}
+
+impl Shadertoy {
+    /// Float so the feedback doesn't band
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// `spirv` is a compiled fragment shader, e.g.
+    /// `include_bytes!("shadertoy/trails.frag.spv")`. Fails if it isn't
+    /// SPIR-V.
+    pub fn new(device: &wgpu::Device, spirv: &[u8]) -> Result<Self, RendererError> {
+        check_spirv(spirv)?;
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+                component_type: wgpu::TextureComponentType::Float,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadertoy bind group layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadertoy pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module =
+            device.create_shader_module(wgpu::include_spirv!("post/fullscreen.vert.spv"));
+        let fs_module = device.create_shader_module(wgpu::util::make_spirv(spirv));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadertoy pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor::default()),
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: Self::FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        // Nearest, so canvas pixels come out as they are
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadertoy sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadertoy uniforms"),
+            size: std::mem::size_of::<ShadertoyUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let now = Instant::now();
+        Ok(Self {
+            pipeline,
+            layout,
+            sampler,
+            uniforms,
+            targets: None,
+            size: (0, 0),
+            started: now,
+            last_frame: now,
+            frame: 0,
+            mouse: [0.0; 4],
+        })
+    }
+
+    pub fn set_mouse(&mut self, mouse: [f32; 4]) {
+        self.mouse = mouse;
+    }
+
+    /// What the last `run` rendered
+    pub fn output(&self) -> Option<&wgpu::Texture> {
+        self.targets.as_ref().map(|[output, _]| output)
+    }
+
+    /// Record the pass for the next frame, reading `canvas`. A new canvas
+    /// size starts over from an empty last frame.
+    pub fn run(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        canvas: &wgpu::Texture,
+        canvas_size: (u32, u32),
+    ) {
+        let fresh = self.targets.is_none() || self.size != canvas_size;
+        if fresh {
+            self.size = canvas_size;
+            self.targets = Some([
+                create_target(device, canvas_size),
+                create_target(device, canvas_size),
+            ]);
+        }
+
+        let now = Instant::now();
+        let uniforms = ShadertoyUniforms {
+            resolution: [canvas_size.0 as f32, canvas_size.1 as f32, 1.0],
+            time: (now - self.started).as_secs_f32(),
+            time_delta: (now - self.last_frame).as_secs_f32(),
+            frame: self.frame,
+            _padding: [0.0; 2],
+            mouse: self.mouse,
+        };
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+        self.last_frame = now;
+        self.frame = self.frame.wrapping_add(1);
+
+        let targets = match &mut self.targets {
+            Some(targets) => targets,
+            None => return,
+        };
+        // The old output is the new last frame
+        targets.swap(0, 1);
+        let [output, previous] = &*targets;
+
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (canvas_view, previous_view, output_view) =
+            (view(canvas), view(previous), view(output));
+
+        // New textures aren't cleared
+        if fresh {
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &previous_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadertoy bind group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&canvas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&previous_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(self.uniforms.slice(..)),
+                },
+            ],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_target(device: &wgpu::Device, (width, height): (u32, u32)) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("shadertoy target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: Shadertoy::FORMAT,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    })
+}
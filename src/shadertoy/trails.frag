@@ -0,0 +1,57 @@
+#version 450
+
+// -----------------------------------------------------------------------------
+//     - Shadertoy prelude -
+//     Copy everything down to `mainImage` into a new shader. Unlike
+//     Shadertoy, y points down: fragCoord, iMouse and texture coordinates
+//     all start at the top left.
+// -----------------------------------------------------------------------------
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_channel0;
+layout(set = 0, binding = 1) uniform texture2D t_channel1;
+layout(set = 0, binding = 2) uniform sampler s_channel;
+
+layout(set = 0, binding = 3) uniform ShadertoyUniforms {
+    // Canvas size in pixels, z is always 1
+    vec3 iResolution;
+    // Seconds since the shader was set
+    float iTime;
+    float iTimeDelta;
+    int iFrame;
+    // xy: position while a button is held, zw: where it was pressed.
+    // z is negative while released, w is negative after the first frame.
+    vec4 iMouse;
+};
+
+// The canvas
+#define iChannel0 sampler2D(t_channel0, s_channel)
+// What this shader output last frame
+#define iChannel1 sampler2D(t_channel1, s_channel)
+
+void mainImage(out vec4 fragColor, in vec2 fragCoord);
+
+void main() {
+    mainImage(f_color, gl_FragCoord.xy);
+}
+
+// -----------------------------------------------------------------------------
+//     - Trails -
+//     The canvas with a fading trail behind everything that moves, and a
+//     glow around the mouse while a button is held.
+// -----------------------------------------------------------------------------
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    vec2 uv = fragCoord / iResolution.xy;
+    vec4 canvas = texture(iChannel0, uv);
+    vec4 previous = texture(iChannel1, uv);
+
+    vec4 color = max(canvas, previous * 0.92);
+
+    if (iMouse.z > 0.0) {
+        float glow = 1.0 - smoothstep(0.0, 12.0, distance(fragCoord, iMouse.xy));
+        color.rgb += vec3(0.2, 0.4, 1.0) * glow * (0.75 + 0.25 * sin(iTime * 6.0));
+        color.a = max(color.a, glow);
+    }
+
+    fragColor = color;
+}
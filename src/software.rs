@@ -0,0 +1,494 @@
+use futures::future;
+use winit::dpi::PhysicalSize;
+
+use crate::blend::{linear_to_srgb, srgb_to_linear, AlphaMode, BlendMode};
+use crate::compute::{ComputeId, ComputeShader};
+use crate::error::RendererError;
+use crate::filter::FilterMode;
+use crate::format::CanvasFormat;
+use crate::layer::{LayerId, LayerStack};
+use crate::post::PostEffect;
+use crate::readback::CanvasReadback;
+use crate::render::{check_canvas_size, Pixel, PixelBuffer, PresentBackend};
+use crate::scaling::Viewport;
+use crate::surface::{clamp_size, SurfaceState, SurfaceStats};
+
+// -----------------------------------------------------------------------------
+//     - Software backend -
+//     Does what the wgpu backend does, on the cpu: layers are "uploaded" to
+//     copies of their own, drawn as textured quads and blended in linear
+//     space into an sRGB framebuffer. The result should match the gpu give
+//     or take rounding. There are no mipmaps, so trilinear filtering
+//     samples like bilinear.
+// -----------------------------------------------------------------------------
+pub struct SoftwareBackend {
+    size: PhysicalSize<u32>,
+    format: CanvasFormat,
+    filter: FilterMode,
+    surface_state: SurfaceState,
+    frame: PixelBuffer,
+    /// The pixels of each layer as of the last `sync_layers`, these are the
+    /// textures
+    layers: Vec<(LayerId, PixelBuffer)>,
+    upload_bytes: u64,
+}
+
+impl SoftwareBackend {
+    pub fn new(size: PhysicalSize<u32>, format: CanvasFormat, filter: FilterMode) -> Self {
+        let size = clamp_size(size);
+        Self {
+            size,
+            format,
+            filter,
+            surface_state: SurfaceState::new(),
+            frame: PixelBuffer::new(size.width, size.height),
+            layers: Vec::new(),
+            upload_bytes: 0,
+        }
+    }
+}
+
+impl PresentBackend for SoftwareBackend {
+    fn sync_layers(&mut self, stack: &mut LayerStack) -> Result<(), RendererError> {
+        self.upload_bytes = 0;
+        let bytes_per_pixel = self.format.bytes_per_pixel() as u64;
+        self.layers.retain(|(id, _)| stack.get(*id).is_some());
+
+        for canvas_layer in stack.iter_mut() {
+            let layer_id = canvas_layer.id();
+            let pixels = &mut canvas_layer.pixels;
+            check_canvas_size(pixels.width(), pixels.height())?;
+            let dirty = pixels.take_dirty();
+            let existing = self.layers.iter_mut().find(|(id, _)| *id == layer_id);
+
+            match existing {
+                Some((_, texture))
+                    if texture.width() == pixels.width() && texture.height() == pixels.height() =>
+                {
+                    for rect in dirty {
+                        let (x, width) = (rect.x as usize, rect.width as usize);
+                        for y in rect.y as u32..rect.bottom() as u32 {
+                            let (src, dst) = match (pixels.row(y), texture.row_mut(y)) {
+                                (Some(src), Some(dst)) => (src, dst),
+                                _ => continue,
+                            };
+                            dst[x..x + width].copy_from_slice(&src[x..x + width]);
+                        }
+                        self.upload_bytes += rect.area() * bytes_per_pixel;
+                    }
+                }
+                existing => {
+                    self.upload_bytes += pixels.bounds().area() * bytes_per_pixel;
+                    match existing {
+                        Some((_, texture)) => *texture = pixels.clone(),
+                        None => self.layers.push((layer_id, pixels.clone())),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(
+        &mut self,
+        stack: &LayerStack,
+        viewport: Viewport,
+        letterbox_color: Pixel,
+    ) -> Result<(), RendererError> {
+        // There is no swap chain, but frames are skipped the same way
+        if self
+            .surface_state
+            .next_frame(self.size, |_| Ok(()))?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let canvas_size = stack.size();
+        let (width, height) = (self.size.width, self.size.height);
+
+        // Opaque like the clear on the gpu
+        self.frame.fill(Pixel {
+            a: 255,
+            ..letterbox_color
+        });
+
+        // Same as the scissor rect on the gpu
+        let (sx, sy, sw, sh) = match viewport.visible((width, height)) {
+            Some(visible) => visible,
+            None => return Ok(()),
+        };
+
+        // The framebuffer is always sRGB, the layers depend on the format
+        let (mut to_linear, mut decode) = ([0.0; 256], [0.0; 256]);
+        for i in 0..256 {
+            to_linear[i] = srgb_to_linear(i as u8);
+            decode[i] = self.format.decode(i as u8);
+        }
+
+        for canvas_layer in stack.iter().filter(|l| l.visible) {
+            let texture = match self.layers.iter().find(|(id, _)| *id == canvas_layer.id()) {
+                Some((_, texture)) => texture,
+                None => continue,
+            };
+
+            if texture.width() == 0 || texture.height() == 0 {
+                continue;
+            }
+
+            let opacity = canvas_layer.opacity.clamp(0.0, 1.0) as f64;
+            let premultiplied = canvas_layer.pixels.alpha_mode() == AlphaMode::Premultiplied;
+            let [left, top, right, bottom] = viewport.layer_rect(canvas_layer, canvas_size);
+            let (left, top, right, bottom) = (left as f64, top as f64, right as f64, bottom as f64);
+
+            // Pixels whose centers are inside the quad
+            let x0 = ((left - 0.5).ceil() as i64).max(sx as i64);
+            let x1 = ((right - 0.5).ceil() as i64).min((sx + sw) as i64);
+            let y0 = ((top - 0.5).ceil() as i64).max(sy as i64);
+            let y1 = ((bottom - 0.5).ceil() as i64).min((sy + sh) as i64);
+
+            // What `fwidth` gives the shader, the quad is never rotated
+            let texels_per_pixel = (
+                texture.width() as f64 / (right - left),
+                texture.height() as f64 / (bottom - top),
+            );
+
+            for y in y0..y1 {
+                let v = (y as f64 + 0.5 - top) / (bottom - top);
+                let dst_row = match self.frame.row_mut(y as u32) {
+                    Some(row) => row,
+                    None => continue,
+                };
+
+                for x in x0..x1 {
+                    let u = (x as f64 + 0.5 - left) / (right - left);
+
+                    // What the fragment shader outputs
+                    let mut src = sample(texture, &decode, self.filter, (u, v), texels_per_pixel);
+                    if !premultiplied {
+                        src[0] *= src[3];
+                        src[1] *= src[3];
+                        src[2] *= src[3];
+                    }
+                    for c in src.iter_mut() {
+                        *c *= opacity;
+                    }
+
+                    let dst_pixel = &mut dst_row[x as usize];
+                    let dst = [
+                        to_linear[dst_pixel.r as usize],
+                        to_linear[dst_pixel.g as usize],
+                        to_linear[dst_pixel.b as usize],
+                        dst_pixel.a as f64 / 255.0,
+                    ];
+
+                    let [r, g, b, a] = blend_linear(src, dst, canvas_layer.blend_mode);
+                    *dst_pixel = Pixel::new(
+                        linear_to_srgb(r),
+                        linear_to_srgb(g),
+                        linear_to_srgb(b),
+                        (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.size = clamp_size(new_size);
+        self.frame = PixelBuffer::new(self.size.width, self.size.height);
+        self.surface_state.invalidate();
+    }
+
+    fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    fn read_frame(&self) -> Result<PixelBuffer, RendererError> {
+        Ok(self.frame.clone())
+    }
+
+    /// The layer's own copy, there is nothing to wait for
+    fn read_canvas(&self, stack: &LayerStack) -> CanvasReadback {
+        let mut canvas = match self.layers.iter().find(|(id, _)| *id == stack.base_id()) {
+            Some((_, texture)) => texture.clone(),
+            None => stack.base().pixels.clone(),
+        };
+        canvas.set_alpha_mode(stack.base().pixels.alpha_mode());
+        Box::pin(future::ready(Ok(canvas)))
+    }
+
+    /// Nothing is presented, the last frame is still there
+    fn capture_frame(
+        &mut self,
+        _stack: &LayerStack,
+        _viewport: Viewport,
+        _letterbox_color: Pixel,
+    ) -> Result<PixelBuffer, RendererError> {
+        if self.frame.width() == 0 || self.frame.height() == 0 {
+            return Err(RendererError::InvalidOutputSize {
+                width: self.size.width,
+                height: self.size.height,
+            });
+        }
+        Ok(self.frame.clone())
+    }
+
+    fn poll(&self, _wait: bool) {}
+
+    fn upload_bytes(&self) -> u64 {
+        self.upload_bytes
+    }
+
+    fn inject_surface_error(&mut self, error: wgpu::SwapChainError) {
+        self.surface_state.inject_error(error);
+    }
+
+    fn surface_stats(&self) -> SurfaceStats {
+        self.surface_state.stats()
+    }
+
+    fn adapter_info(&self) -> Option<wgpu::AdapterInfo> {
+        None
+    }
+
+    fn filter(&self) -> FilterMode {
+        self.filter
+    }
+
+    fn set_filter(&mut self, filter: FilterMode) {
+        self.filter = filter;
+    }
+
+    fn post_effects(&self) -> &[PostEffect] {
+        &[]
+    }
+
+    /// There are no shaders to run on the cpu
+    fn set_post_effects(&mut self, effects: &[PostEffect]) -> Result<(), RendererError> {
+        match effects.is_empty() {
+            true => Ok(()),
+            false => Err(RendererError::ShadersUnsupported),
+        }
+    }
+
+    fn set_shadertoy(&mut self, shader: Option<&[u8]>) -> Result<(), RendererError> {
+        match shader {
+            Some(_) => Err(RendererError::ShadersUnsupported),
+            None => Ok(()),
+        }
+    }
+
+    fn set_shadertoy_mouse(&mut self, _mouse: [f32; 4]) {}
+
+    fn add_compute(&mut self, _shader: &ComputeShader) -> Result<ComputeId, RendererError> {
+        Err(RendererError::ShadersUnsupported)
+    }
+
+    fn remove_compute(&mut self, _id: ComputeId) {}
+
+    fn set_compute_uniforms(
+        &mut self,
+        _id: ComputeId,
+        _uniforms: &[u8],
+    ) -> Result<(), RendererError> {
+        Err(RendererError::ShadersUnsupported)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Sampling -
+//     What the sampler returns at texture coordinates `uv`, with the colour
+//     decoded to linear and the alpha left as it is. Addresses are clamped
+//     to the edge like the gpu sampler.
+// -----------------------------------------------------------------------------
+fn sample(
+    texture: &PixelBuffer,
+    decode: &[f64; 256],
+    filter: FilterMode,
+    (u, v): (f64, f64),
+    texels_per_pixel: (f64, f64),
+) -> [f64; 4] {
+    let (width, height) = (texture.width() as f64, texture.height() as f64);
+    let texel = |x: i64, y: i64| {
+        let x = x.max(0).min(texture.width() as i64 - 1) as u32;
+        let y = y.max(0).min(texture.height() as i64 - 1) as u32;
+        let p = texture.get(x, y).copied().unwrap_or_default();
+        [
+            decode[p.r as usize],
+            decode[p.g as usize],
+            decode[p.b as usize],
+            p.a as f64 / 255.0,
+        ]
+    };
+
+    let (x, y) = match filter {
+        FilterMode::Nearest => {
+            return texel((u * width).floor() as i64, (v * height).floor() as i64);
+        }
+        FilterMode::Bilinear | FilterMode::Trilinear => (u * width, v * height),
+        // Same as `sharp_bilinear` in shader.frag
+        FilterMode::SharpBilinear => {
+            let sharpen = |t: f64, texels_per_pixel: f64| {
+                let seam = (t + 0.5).floor();
+                seam + ((t - seam) / texels_per_pixel).clamp(-0.5, 0.5)
+            };
+            (
+                sharpen(u * width, texels_per_pixel.0),
+                sharpen(v * height, texels_per_pixel.1),
+            )
+        }
+    };
+
+    // Texel centres are at .5
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let (tl, tr) = (texel(x0, y0), texel(x0 + 1, y0));
+    let (bl, br) = (texel(x0, y0 + 1), texel(x0 + 1, y0 + 1));
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+    let mut out = [0.0; 4];
+    for (i, c) in out.iter_mut().enumerate() {
+        *c = lerp(lerp(tl[i], tr[i], fx), lerp(bl[i], br[i], fx), fy);
+    }
+    out
+}
+
+// -----------------------------------------------------------------------------
+//     - Linear blend -
+//     The fixed function blending set up by `gpu_blend`, with premultiplied
+//     colours in linear space. The destination alpha is left as is.
+// -----------------------------------------------------------------------------
+fn blend_linear(src: [f64; 4], dst: [f64; 4], mode: BlendMode) -> [f64; 4] {
+    let (sa, a) = (src[3], dst[3]);
+
+    let channel: &dyn Fn(f64, f64) -> f64 = match mode {
+        BlendMode::Replace => &|s, _| s,
+        BlendMode::SourceOver => &|s, d| s + d * (1.0 - sa),
+        BlendMode::Additive => &|s, d| s + d,
+        BlendMode::Multiply => &|s, d| s * d + d * (1.0 - sa),
+        BlendMode::Screen => &|s, d| s + d * (1.0 - s),
+        BlendMode::Subtract => &|s, d| d - s,
+        BlendMode::Darken => &|s: f64, d: f64| s.min(d),
+        BlendMode::Lighten => &|s: f64, d: f64| s.max(d),
+    };
+
+    // The target is unorm, so everything is clamped
+    let clamp = |c: f64| c.clamp(0.0, 1.0);
+
+    [
+        clamp(channel(src[0], dst[0])),
+        clamp(channel(src[1], dst[1])),
+        clamp(channel(src[2], dst[2])),
+        clamp(a),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blend::BlendMode;
+    use crate::config::RendererConfig;
+    use crate::render::test_util::rgba;
+    use crate::render::{Pixel, PixelBuffer, Renderer};
+    use crate::scaling::ScalingMode;
+
+    /// 2x2 canvas with a different colour in every corner
+    fn corners(renderer: &mut Renderer) -> [Pixel; 4] {
+        let colors = [
+            Pixel::new(255, 0, 0, 255),
+            Pixel::new(0, 255, 0, 255),
+            Pixel::new(0, 0, 255, 255),
+            Pixel::new(200, 100, 50, 255),
+        ];
+        for (i, &color) in colors.iter().enumerate() {
+            renderer.pixels().set(i as u32 % 2, i as u32 / 2, color);
+        }
+        colors
+    }
+
+    fn rendered(renderer: &mut Renderer) -> PixelBuffer {
+        renderer.draw().unwrap();
+        renderer.render().unwrap();
+        renderer.read_frame().unwrap()
+    }
+
+    #[test]
+    fn renders_the_canvas_at_1_to_1() {
+        let mut renderer = Renderer::new_software(2, 2, 2, 2).unwrap();
+        let colors = corners(&mut renderer);
+        let frame = rendered(&mut renderer);
+
+        for (x, y, p) in frame.enumerate() {
+            let expected = colors[(y * 2 + x) as usize];
+            assert_eq!(rgba(p), rgba(&expected), "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn stretches_with_nearest() {
+        let mut renderer = Renderer::new_software(2, 2, 4, 6).unwrap();
+        let colors = corners(&mut renderer);
+        let frame = rendered(&mut renderer);
+
+        for (x, y, p) in frame.enumerate() {
+            let expected = colors[((y / 3) * 2 + x / 2) as usize];
+            assert_eq!(rgba(p), rgba(&expected), "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn letterboxes_when_fitting() {
+        let config = RendererConfig {
+            scaling_mode: ScalingMode::AspectFit,
+            ..RendererConfig::default()
+        };
+        let mut renderer = Renderer::software_with_config(2, 2, 6, 4, config).unwrap();
+        // Alpha is ignored, the frame is opaque
+        renderer.set_letterbox_color(Pixel::new(10, 20, 30, 0));
+        let colors = corners(&mut renderer);
+        let frame = rendered(&mut renderer);
+
+        // Scaled by 2 and centred, with a column either side
+        for (x, y, p) in frame.enumerate() {
+            let expected = match x {
+                0 | 5 => Pixel::new(10, 20, 30, 255),
+                x => colors[((y / 2) * 2 + (x - 1) / 2) as usize],
+            };
+            assert_eq!(rgba(p), rgba(&expected), "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn layers_are_blended() {
+        let mut renderer = Renderer::new_software(2, 1, 2, 1).unwrap();
+        renderer.pixels().fill(Pixel::new(255, 255, 255, 255));
+
+        let id = renderer.layers_mut().add(2, 1);
+        let layer = renderer.layers_mut().get_mut(id).unwrap();
+        layer.blend_mode = BlendMode::Multiply;
+        layer.pixels.set(0, 0, Pixel::new(255, 0, 0, 255));
+
+        let frame = rendered(&mut renderer);
+        let pixels = frame.row(0).unwrap();
+        assert_eq!(rgba(&pixels[0]), (255, 0, 0, 255));
+        // Transparent, so the base shows through
+        assert_eq!(rgba(&pixels[1]), (255, 255, 255, 255));
+    }
+
+    #[test]
+    fn capturing_without_a_size_fails() {
+        let mut renderer = Renderer::new_software(2, 2, 2, 2).unwrap();
+        renderer.render().unwrap();
+        assert_eq!(renderer.capture_frame().unwrap().width(), 2);
+
+        renderer.resize(winit::dpi::PhysicalSize::new(0, 2));
+        renderer.render().unwrap();
+        assert!(renderer.capture_frame().is_err());
+    }
+}
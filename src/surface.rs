@@ -0,0 +1,195 @@
+use winit::dpi::PhysicalSize;
+
+use crate::error::RendererError;
+use crate::render::MAX_TEXTURE_SIZE;
+
+// -----------------------------------------------------------------------------
+//     - Surface stats -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SurfaceStats {
+    pub presented: u64,
+    /// Frames dropped because the window was minimized, the swap chain
+    /// timed out or had to be recreated
+    pub skipped: u64,
+    pub recreated: u64,
+}
+
+// -----------------------------------------------------------------------------
+//     - Surface state -
+//     Decides what happens to each frame: skipped while the window has no
+//     size, swap chain recreated after a resize or when it went out of date,
+//     and errors either recovered from or passed on. Shared by the
+//     backends so they behave the same.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct SurfaceState {
+    needs_recreate: bool,
+    /// Returned instead of the next frame, see `inject_error`
+    injected: Option<wgpu::SwapChainError>,
+    stats: SurfaceStats,
+}
+
+impl SurfaceState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recreate the swap chain before the next frame
+    pub fn invalidate(&mut self) {
+        self.needs_recreate = true;
+    }
+
+    /// Test hook: the next frame fails with `error` as if the surface
+    /// returned it
+    pub fn inject_error(&mut self, error: wgpu::SwapChainError) {
+        self.injected = Some(error);
+    }
+
+    pub fn stats(&self) -> SurfaceStats {
+        self.stats
+    }
+
+    /// Get the next frame with `acquire`, which is passed true when the
+    /// swap chain has to be recreated first. `None` means the frame should
+    /// be skipped.
+    pub fn next_frame<T>(
+        &mut self,
+        size: PhysicalSize<u32>,
+        acquire: impl FnOnce(bool) -> Result<T, wgpu::SwapChainError>,
+    ) -> Result<Option<T>, RendererError> {
+        // Minimized
+        if size.width == 0 || size.height == 0 {
+            self.stats.skipped += 1;
+            return Ok(None);
+        }
+
+        let frame = match self.injected.take() {
+            Some(error) => Err(error),
+            None => {
+                let recreate = std::mem::replace(&mut self.needs_recreate, false);
+                if recreate {
+                    self.stats.recreated += 1;
+                }
+                acquire(recreate)
+            }
+        };
+
+        match frame {
+            Ok(frame) => {
+                self.stats.presented += 1;
+                Ok(Some(frame))
+            }
+            Err(wgpu::SwapChainError::Timeout) => {
+                self.stats.skipped += 1;
+                Ok(None)
+            }
+            Err(wgpu::SwapChainError::Outdated) | Err(wgpu::SwapChainError::Lost) => {
+                self.stats.skipped += 1;
+                self.needs_recreate = true;
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Sizes past what a texture can hold are clamped, zero is left alone
+pub fn clamp_size(size: PhysicalSize<u32>) -> PhysicalSize<u32> {
+    PhysicalSize::new(
+        size.width.min(MAX_TEXTURE_SIZE),
+        size.height.min(MAX_TEXTURE_SIZE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(4, 4);
+
+    /// Acquire with an injected error, then once more to see whether the
+    /// swap chain is recreated after it
+    fn inject(error: wgpu::SwapChainError) -> (Result<Option<()>, RendererError>, bool) {
+        let mut state = SurfaceState::new();
+        state.inject_error(error);
+        let first = state.next_frame(SIZE, |_| -> Result<(), _> { panic!("not acquired") });
+
+        let mut recreated = false;
+        if first.is_ok() {
+            let second = state.next_frame(SIZE, |recreate| {
+                recreated = recreate;
+                Ok(())
+            });
+            assert_eq!(second.unwrap(), Some(()));
+        }
+
+        (first, recreated)
+    }
+
+    #[test]
+    fn lost_recreates() {
+        let (first, recreated) = inject(wgpu::SwapChainError::Lost);
+        assert_eq!(first.unwrap(), None);
+        assert!(recreated);
+    }
+
+    #[test]
+    fn outdated_recreates() {
+        let (first, recreated) = inject(wgpu::SwapChainError::Outdated);
+        assert_eq!(first.unwrap(), None);
+        assert!(recreated);
+    }
+
+    #[test]
+    fn timeout_skips() {
+        let (first, recreated) = inject(wgpu::SwapChainError::Timeout);
+        assert_eq!(first.unwrap(), None);
+        assert!(!recreated);
+    }
+
+    #[test]
+    fn out_of_memory_is_an_error() {
+        match inject(wgpu::SwapChainError::OutOfMemory).0 {
+            Err(RendererError::Surface(wgpu::SwapChainError::OutOfMemory)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn stats_count_every_frame() {
+        let mut state = SurfaceState::new();
+        let acquire = |_| Ok(());
+
+        state.next_frame(SIZE, acquire).unwrap();
+        state.inject_error(wgpu::SwapChainError::Outdated);
+        state.next_frame(SIZE, acquire).unwrap();
+        state.next_frame(SIZE, acquire).unwrap();
+        // Minimized
+        state.next_frame(PhysicalSize::new(0, 4), acquire).unwrap();
+
+        let expected = SurfaceStats {
+            presented: 2,
+            skipped: 2,
+            recreated: 1,
+        };
+        assert_eq!(state.stats(), expected);
+    }
+
+    #[test]
+    fn invalidate_recreates_once() {
+        let mut state = SurfaceState::new();
+        state.invalidate();
+
+        let mut recreates = Vec::new();
+        for _ in 0..2 {
+            state
+                .next_frame(SIZE, |recreate| {
+                    recreates.push(recreate);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert_eq!(recreates, [true, false]);
+    }
+}
@@ -0,0 +1,850 @@
+// Text API, the demo doesn't draw any text
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+
+use crate::blend::BlendMode;
+use crate::render::{Pixel, PixelBuffer};
+
+/// Tab stops are this many spaces apart
+const TAB_WIDTH: i32 = 4;
+
+/// Largest glyph width and height a font can have, so a malformed size
+/// can't ask for gigabytes
+const MAX_GLYPH_SIZE: u32 = 256;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x06;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+/// Size of the fixed part of the header, glyphs can't start before it ends
+const PSF2_HEADER_SIZE: u32 = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+
+/// Width, height and the offset of the bottom left corner from the origin,
+/// as in BDF's BBX
+type BoundingBox = (u32, u32, i32, i32);
+
+// -----------------------------------------------------------------------------
+//     - Font error -
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum FontError {
+    Io(std::io::Error),
+    /// Malformed BDF, with the line number (starting at 1)
+    Bdf {
+        line: usize,
+        message: String,
+    },
+    /// Malformed PSF
+    Psf(String),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::Io(e) => write!(f, "failed to read font: {}", e),
+            FontError::Bdf { line, message } => write!(f, "bdf line {}: {}", line, message),
+            FontError::Psf(message) => write!(f, "psf: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+impl From<std::io::Error> for FontError {
+    fn from(e: std::io::Error) -> Self {
+        FontError::Io(e)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Glyph -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Glyph {
+    width: u32,
+    height: u32,
+    /// From the pen position to the left edge of the bitmap
+    left: i32,
+    /// From the top of the line to the top edge of the bitmap
+    top: i32,
+    advance: i32,
+    bits: Vec<bool>,
+}
+
+impl Glyph {
+    /// Read `height` rows of packed, msb first, bits. Missing bytes are
+    /// blank, so there's always a bit for every pixel.
+    fn from_rows(width: u32, height: u32, rows: &[u8]) -> Self {
+        let stride = (width as usize).div_ceil(8);
+        let mut bits = Vec::with_capacity(width as usize * height as usize);
+
+        for y in 0..height as usize {
+            let row = rows.get(y * stride..).unwrap_or(&[]);
+            for x in 0..width as usize {
+                let byte = row.get(x / 8).copied().unwrap_or(0);
+                bits.push(byte & (0x80 >> (x % 8)) != 0);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            left: 0,
+            top: 0,
+            advance: width as i32,
+            bits,
+        }
+    }
+
+    pub fn advance(&self) -> i32 {
+        self.advance
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Font -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Font {
+    glyphs: HashMap<char, Glyph>,
+    /// Drawn in place of characters the font doesn't have
+    fallback: Option<Glyph>,
+    line_height: u32,
+}
+
+impl Font {
+    /// 6x10 fixed width, ASCII only.
+    pub fn builtin() -> Self {
+        Self::from_bdf(include_str!("../fonts/6x10.bdf")).expect("the builtin font is valid")
+    }
+
+    /// Load a BDF or PSF (v1 or v2) font, the format is picked from the
+    /// content and not the file extension.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FontError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// `open` for a font that's already in memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FontError> {
+        if bytes.starts_with(&PSF1_MAGIC) || bytes.starts_with(&PSF2_MAGIC) {
+            return Self::from_psf(bytes);
+        }
+
+        match std::str::from_utf8(bytes) {
+            Ok(src) => Self::from_bdf(src),
+            Err(e) => {
+                let valid = &bytes[..e.valid_up_to()];
+                Err(FontError::Bdf {
+                    line: valid.iter().filter(|&&b| b == b'\n').count() + 1,
+                    message: "not a psf font and not valid utf-8".into(),
+                })
+            }
+        }
+    }
+
+    pub fn line_height(&self) -> u32 {
+        self.line_height
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or(self.fallback.as_ref())
+    }
+
+    /// Width and height of the box `text` covers when drawn.
+    pub fn measure(&self, text: &str) -> (u32, u32) {
+        if text.is_empty() {
+            return (0, 0);
+        }
+
+        let mut width = 0;
+        let mut lines = 0;
+        self.layout(text, |_, pen_x, line| {
+            width = width.max(pen_x);
+            lines = lines.max(line + 1);
+        });
+
+        (width.max(0) as u32, lines.saturating_mul(self.line_height))
+    }
+
+    /// Walk the text, calling `f` with every glyph (`None` for the end of
+    /// a line), the pen x after it and the line number.
+    fn layout<'a>(&'a self, text: &str, mut f: impl FnMut(Option<(&'a Glyph, i32)>, i32, u32)) {
+        let space = self.glyph(' ').map(|g| g.advance).unwrap_or(0);
+        let tab = space.saturating_mul(TAB_WIDTH).max(1);
+
+        let mut pen_x = 0;
+        let mut line = 0;
+
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    f(None, pen_x, line);
+                    pen_x = 0;
+                    line += 1;
+                }
+                '\r' => {}
+                '\t' => {
+                    pen_x = (pen_x / tab).saturating_add(1).saturating_mul(tab);
+                    f(None, pen_x, line);
+                }
+                c => {
+                    if let Some(glyph) = self.glyph(c) {
+                        let x = pen_x;
+                        pen_x = pen_x.saturating_add(glyph.advance);
+                        f(Some((glyph, x)), pen_x, line);
+                    }
+                }
+            }
+        }
+        f(None, pen_x, line);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - BDF -
+    // -----------------------------------------------------------------------------
+    pub fn from_bdf(src: &str) -> Result<Self, FontError> {
+        let mut glyphs = HashMap::new();
+        let mut bounding_box = (0, 0, 0, 0);
+        let mut ascent = None;
+        let mut descent = None;
+        let mut default_char = None;
+
+        // Glyph in progress: (encoding, advance, bbx)
+        let mut current: Option<(i64, i32, BoundingBox)> = None;
+        let mut lines = src.lines().enumerate().map(|(i, l)| (i + 1, l.trim()));
+
+        while let Some((line_no, line)) = lines.next() {
+            let err = |message: &str| FontError::Bdf {
+                line: line_no,
+                message: message.into(),
+            };
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let numbers = || -> Result<Vec<i64>, FontError> {
+                line.split_whitespace()
+                    .skip(1)
+                    .map(|n| n.parse().map_err(|_| err("expected a number")))
+                    .collect()
+            };
+            let fit = |n: Option<&i64>, keyword: &str| -> Result<Option<i32>, FontError> {
+                n.map(|&n| i32::try_from(n))
+                    .transpose()
+                    .map_err(|_| err(&format!("{} is out of range", keyword)))
+            };
+
+            match keyword {
+                "FONTBOUNDINGBOX" => {
+                    bounding_box = parse_bounding_box(&numbers()?)
+                        .map_err(|e| err(&format!("FONTBOUNDINGBOX {}", e)))?;
+                }
+                "FONT_ASCENT" => ascent = fit(numbers()?.first(), "FONT_ASCENT")?,
+                "FONT_DESCENT" => descent = fit(numbers()?.first(), "FONT_DESCENT")?,
+                "DEFAULT_CHAR" => default_char = numbers()?.first().copied(),
+                "STARTCHAR" => current = Some((-1, bounding_box.0 as i32, bounding_box)),
+                "ENCODING" => {
+                    let encoding = *numbers()?.first().ok_or_else(|| err("missing encoding"))?;
+                    current
+                        .as_mut()
+                        .ok_or_else(|| err("ENCODING outside of a glyph"))?
+                        .0 = encoding;
+                }
+                "DWIDTH" => {
+                    let advance =
+                        fit(numbers()?.first(), "DWIDTH")?.ok_or_else(|| err("missing width"))?;
+                    current
+                        .as_mut()
+                        .ok_or_else(|| err("DWIDTH outside of a glyph"))?
+                        .1 = advance;
+                }
+                "BBX" => {
+                    let bbx =
+                        parse_bounding_box(&numbers()?).map_err(|e| err(&format!("BBX {}", e)))?;
+                    current
+                        .as_mut()
+                        .ok_or_else(|| err("BBX outside of a glyph"))?
+                        .2 = bbx;
+                }
+                "BITMAP" => {
+                    let (encoding, advance, (w, h, x, y)) = current
+                        .take()
+                        .ok_or_else(|| err("BITMAP outside of a glyph"))?;
+
+                    let stride = (w as usize).div_ceil(8);
+                    let mut rows = Vec::new();
+                    for _ in 0..h {
+                        let (line_no, row) = lines
+                            .next()
+                            .ok_or_else(|| err("unexpected end of bitmap"))?;
+                        let not_hex = || FontError::Bdf {
+                            line: line_no,
+                            message: "bitmap rows are hex".into(),
+                        };
+                        // Slicing below is by byte
+                        if !row.is_ascii() {
+                            return Err(not_hex());
+                        }
+                        let row = (0..row.len() / 2)
+                            .map(|i| u8::from_str_radix(&row[i * 2..i * 2 + 2], 16))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|_| not_hex())?;
+                        rows.extend((0..stride).map(|i| row.get(i).copied().unwrap_or(0)));
+                    }
+
+                    // Vertical position is fixed up once the ascent is known
+                    let mut glyph = Glyph::from_rows(w, h, &rows);
+                    glyph.left = x;
+                    glyph.top = y.saturating_add(h as i32);
+                    glyph.advance = advance;
+
+                    // Negative encodings are glyphs without a code point
+                    if encoding >= 0 {
+                        glyphs.insert(encoding, glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let ascent = ascent.unwrap_or((bounding_box.1 as i32).saturating_add(bounding_box.3));
+        let descent = descent.unwrap_or(bounding_box.3.saturating_neg());
+
+        for glyph in glyphs.values_mut() {
+            glyph.top = ascent.saturating_sub(glyph.top);
+        }
+
+        // DEFAULT_CHAR is often 0, which few fonts have a glyph for
+        let fallback = default_char
+            .and_then(|c| glyphs.get(&c))
+            .or_else(|| glyphs.get(&('?' as i64)))
+            .cloned();
+        let glyphs = glyphs
+            .into_iter()
+            .filter_map(|(encoding, glyph)| Some((std::char::from_u32(encoding as u32)?, glyph)))
+            .collect();
+
+        Ok(Self {
+            glyphs,
+            fallback,
+            line_height: ascent.saturating_add(descent).max(0) as u32,
+        })
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - PSF -
+    // -----------------------------------------------------------------------------
+    pub fn from_psf(bytes: &[u8]) -> Result<Self, FontError> {
+        let err = |message: &str| FontError::Psf(message.into());
+
+        let (glyph_count, width, height, glyph_size, glyph_start, table) =
+            if bytes.starts_with(&PSF1_MAGIC) {
+                let mode = *bytes.get(2).ok_or_else(|| err("truncated header"))?;
+                let height = *bytes.get(3).ok_or_else(|| err("truncated header"))? as u32;
+                let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+                let has_table = mode & PSF1_MODE_HAS_TABLE != 0;
+                (count, 8, height, height as usize, 4, has_table)
+            } else if bytes.starts_with(&PSF2_MAGIC) {
+                let field = |i: usize| -> Result<u32, FontError> {
+                    let b = bytes
+                        .get(i * 4..i * 4 + 4)
+                        .ok_or_else(|| err("truncated header"))?;
+                    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                };
+                let (header_size, flags, count) = (field(2)?, field(3)?, field(4)?);
+                let (glyph_size, height, width) = (field(5)?, field(6)?, field(7)?);
+                if header_size < PSF2_HEADER_SIZE {
+                    return Err(err("header is smaller than 32 bytes"));
+                }
+                let has_table = flags & PSF2_HAS_UNICODE_TABLE != 0;
+                (
+                    count as usize,
+                    width,
+                    height,
+                    glyph_size as usize,
+                    header_size as usize,
+                    has_table,
+                )
+            } else {
+                return Err(err("bad magic"));
+            };
+
+        if glyph_size == 0 {
+            return Err(err("glyph size is 0"));
+        }
+        if width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+            return Err(err("glyphs are larger than 256x256"));
+        }
+        let stride = (width as usize).div_ceil(8);
+        match stride.checked_mul(height as usize) {
+            Some(bitmap) if bitmap <= glyph_size => {}
+            _ => return Err(err("glyph size is smaller than the glyph bitmap")),
+        }
+
+        let table_start = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(glyph_start));
+        let data = table_start
+            .and_then(|table_start| bytes.get(glyph_start..table_start))
+            .ok_or_else(|| err("truncated glyph data"))?;
+        let table_start = glyph_start + data.len();
+
+        let glyph_list = data
+            .chunks(glyph_size)
+            .map(|rows| Glyph::from_rows(width, height, rows))
+            .collect::<Vec<_>>();
+
+        // Which characters each glyph stands for
+        let mut mapping: Vec<(char, usize)> = Vec::new();
+
+        if !table {
+            mapping
+                .extend((0..glyph_count).filter_map(|i| Some((std::char::from_u32(i as u32)?, i))));
+        } else if bytes.starts_with(&PSF1_MAGIC) {
+            let entries = bytes[table_start..]
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]));
+            let (mut glyph, mut in_sequence) = (0, false);
+            for entry in entries {
+                match entry {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    // Combining sequences can't be drawn from a single char
+                    PSF1_START_SEQ => in_sequence = true,
+                    c if !in_sequence => {
+                        if let Some(c) = std::char::from_u32(c as u32) {
+                            mapping.push((c, glyph));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        } else {
+            for (glyph, entry) in bytes[table_start..]
+                .split(|&b| b == PSF2_SEPARATOR)
+                .enumerate()
+            {
+                let singles = entry.split(|&b| b == PSF2_START_SEQ).next().unwrap_or(&[]);
+                let singles =
+                    std::str::from_utf8(singles).map_err(|_| err("unicode table is not utf-8"))?;
+                mapping.extend(singles.chars().map(|c| (c, glyph)));
+            }
+        }
+
+        let glyphs = mapping
+            .into_iter()
+            .filter_map(|(c, i)| Some((c, glyph_list.get(i)?.clone())))
+            .collect::<HashMap<_, _>>();
+
+        let fallback = glyphs.get(&'?').cloned();
+
+        Ok(Self {
+            glyphs,
+            fallback,
+            line_height: height,
+        })
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Text drawing -
+// -----------------------------------------------------------------------------
+impl PixelBuffer {
+    /// Draw text with its top left corner at (x, y), blended with `mode`.
+    /// Clipped to the buffer.
+    pub fn draw_text(
+        &mut self,
+        font: &Font,
+        x: i32,
+        y: i32,
+        text: &str,
+        color: Pixel,
+        mode: BlendMode,
+    ) {
+        let line_height = font.line_height() as i32;
+
+        font.layout(text, |glyph, _, line| {
+            let (glyph, pen_x) = match glyph {
+                Some(g) => g,
+                None => return,
+            };
+
+            let left = x.saturating_add(pen_x).saturating_add(glyph.left);
+            let top = (line as i32)
+                .saturating_mul(line_height)
+                .saturating_add(y)
+                .saturating_add(glyph.top);
+
+            for gy in 0..glyph.height {
+                for gx in 0..glyph.width {
+                    if glyph.bits[(gy * glyph.width + gx) as usize] {
+                        let (px, py) = (
+                            left.saturating_add(gx as i32),
+                            top.saturating_add(gy as i32),
+                        );
+                        self.plot(px, py, color, mode);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Parse `w h x y` from a BBX or FONTBOUNDINGBOX line
+fn parse_bounding_box(values: &[i64]) -> Result<BoundingBox, &'static str> {
+    let (w, h, x, y) = match *values {
+        [w, h, x, y] => (w, h, x, y),
+        _ => return Err("needs four values"),
+    };
+    if w < 0 || h < 0 {
+        return Err("has a negative size");
+    }
+    if w > MAX_GLYPH_SIZE as i64 || h > MAX_GLYPH_SIZE as i64 {
+        return Err("is larger than 256x256");
+    }
+
+    let fit = |n: i64| i32::try_from(n).map_err(|_| "is out of range");
+    Ok((fit(w)? as u32, fit(h)? as u32, fit(x)?, fit(y)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of a psf2 font without a unicode table
+    fn psf2(count: u32, glyph_size: u32, height: u32, width: u32) -> Vec<u8> {
+        let mut bytes = PSF2_MAGIC.to_vec();
+        for field in &[0, PSF2_HEADER_SIZE, 0, count, glyph_size, height, width] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// A bdf font with a single glyph for 'A'
+    fn bdf(bounding_box: &str, bbx: &str, bitmap: &str) -> String {
+        format!(
+            "STARTFONT 2.1\nFONTBOUNDINGBOX {}\nSTARTCHAR A\nENCODING 65\nDWIDTH 4 0\n\
+             BBX {}\nBITMAP\n{}\nENDCHAR\nENDFONT\n",
+            bounding_box, bbx, bitmap
+        )
+    }
+
+    fn psf_error(bytes: &[u8]) -> String {
+        match Font::from_psf(bytes) {
+            Err(FontError::Psf(message)) => message,
+            other => panic!("expected a psf error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn bdf_error(src: &str) -> (usize, String) {
+        match Font::from_bdf(src) {
+            Err(FontError::Bdf { line, message }) => (line, message),
+            other => panic!("expected a bdf error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn psf_bad_magic() {
+        assert_eq!(psf_error(b"not a font"), "bad magic");
+    }
+
+    #[test]
+    fn psf_truncated_header() {
+        assert_eq!(psf_error(&PSF1_MAGIC), "truncated header");
+        assert_eq!(psf_error(&psf2(1, 8, 8, 8)[..20]), "truncated header");
+    }
+
+    #[test]
+    fn psf_short_header() {
+        let mut bytes = psf2(1, 8, 8, 8);
+        bytes[8..12].copy_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        assert_eq!(psf_error(&bytes), "header is smaller than 32 bytes");
+    }
+
+    #[test]
+    fn psf_zero_glyph_size() {
+        assert_eq!(psf_error(&psf2(1, 0, 8, 8)), "glyph size is 0");
+        // psf1 glyphs are one byte per row
+        assert_eq!(psf_error(&[0x36, 0x04, 0, 0]), "glyph size is 0");
+    }
+
+    #[test]
+    fn psf_oversized_glyphs() {
+        let bytes = psf2(1, u32::MAX, 100_000, 100_000);
+        assert_eq!(psf_error(&bytes), "glyphs are larger than 256x256");
+    }
+
+    #[test]
+    fn psf_glyph_size_smaller_than_bitmap() {
+        let mut bytes = psf2(1, 8, 8, 9);
+        bytes.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            psf_error(&bytes),
+            "glyph size is smaller than the glyph bitmap"
+        );
+    }
+
+    #[test]
+    fn psf_truncated_glyph_data() {
+        let mut bytes = psf2(2, 8, 8, 8);
+        bytes.extend_from_slice(&[0; 12]);
+        assert_eq!(psf_error(&bytes), "truncated glyph data");
+        // count * glyph size doesn't fit in a usize
+        assert_eq!(
+            psf_error(&psf2(u32::MAX, u32::MAX, 1, 8)),
+            "truncated glyph data"
+        );
+    }
+
+    #[test]
+    fn psf_glyphs_are_read() {
+        let mut bytes = psf2(66, 2, 2, 3);
+        // Every glyph blank except 'A'
+        bytes.extend_from_slice(&[0; 65 * 2]);
+        bytes.extend_from_slice(&[0b1010_0000, 0b0100_0000]);
+
+        let font = Font::from_psf(&bytes).unwrap();
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!((glyph.width, glyph.height, font.line_height()), (3, 2, 2));
+        assert_eq!(glyph.bits, [true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn short_rows_are_blank() {
+        let glyph = Glyph::from_rows(10, 3, &[0xff, 0xc0, 0x80]);
+        assert_eq!(glyph.bits.len(), 30);
+        assert_eq!(glyph.bits.iter().filter(|&&b| b).count(), 11);
+    }
+
+    #[test]
+    fn bdf_multibyte_row() {
+        let src = bdf("4 1 0 0", "4 1 0 0", "é0");
+        assert_eq!(bdf_error(&src), (8, "bitmap rows are hex".into()));
+    }
+
+    #[test]
+    fn bdf_row_not_hex() {
+        let src = bdf("4 1 0 0", "4 1 0 0", "G0");
+        assert_eq!(bdf_error(&src), (8, "bitmap rows are hex".into()));
+    }
+
+    #[test]
+    fn bdf_negative_sizes() {
+        let src = bdf("4 -1 0 0", "4 1 0 0", "F0");
+        let message = "FONTBOUNDINGBOX has a negative size".into();
+        assert_eq!(bdf_error(&src), (2, message));
+
+        let src = bdf("4 1 0 0", "-4 1 0 0", "F0");
+        assert_eq!(bdf_error(&src), (6, "BBX has a negative size".into()));
+    }
+
+    #[test]
+    fn bdf_bounding_box_out_of_range() {
+        let src = bdf("4 1 0 0", "4 1 0 4294967296", "F0");
+        assert_eq!(bdf_error(&src), (6, "BBX is out of range".into()));
+    }
+
+    #[test]
+    fn bdf_oversized_bounding_box() {
+        let src = bdf("4 1 0 0", "100000 100000 0 0", "F0");
+        assert_eq!(bdf_error(&src), (6, "BBX is larger than 256x256".into()));
+
+        let src = bdf("4 257 0 0", "4 1 0 0", "F0");
+        let message = "FONTBOUNDINGBOX is larger than 256x256".into();
+        assert_eq!(bdf_error(&src), (2, message));
+
+        // The largest size is fine
+        let rows = vec!["F0"; 256].join("\n");
+        assert!(Font::from_bdf(&bdf("4 256 0 0", "4 256 0 0", &rows)).is_ok());
+    }
+
+    #[test]
+    fn bdf_bounding_box_needs_four_values() {
+        let src = bdf("4 1 0", "4 1 0 0", "F0");
+        let message = "FONTBOUNDINGBOX needs four values".into();
+        assert_eq!(bdf_error(&src), (2, message));
+    }
+
+    #[test]
+    fn bdf_not_a_number() {
+        let src = bdf("4 1 0 0", "4 one 0 0", "F0");
+        assert_eq!(bdf_error(&src), (6, "expected a number".into()));
+    }
+
+    #[test]
+    fn bdf_outside_of_a_glyph() {
+        let (_, message) = bdf_error("ENCODING 65\n");
+        assert_eq!(message, "ENCODING outside of a glyph");
+        let (_, message) = bdf_error("BITMAP\n");
+        assert_eq!(message, "BITMAP outside of a glyph");
+    }
+
+    #[test]
+    fn bdf_unexpected_end_of_bitmap() {
+        let src = "STARTCHAR A\nENCODING 65\nBBX 4 2 0 0\nBITMAP\nF0";
+        assert_eq!(bdf_error(src), (4, "unexpected end of bitmap".into()));
+    }
+
+    /// The buffer as rows of '#' for white and '.' for anything else
+    fn drawn(buffer: &PixelBuffer) -> Vec<String> {
+        buffer
+            .rows()
+            .map(|row| {
+                row.iter()
+                    .map(|p| if p.r == 255 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bdf_metrics_out_of_range() {
+        let src = "FONT_ASCENT 4294967296\n";
+        assert_eq!(bdf_error(src), (1, "FONT_ASCENT is out of range".into()));
+        let src = "FONT_ASCENT 8\nFONT_DESCENT -2147483649\n";
+        assert_eq!(bdf_error(src), (2, "FONT_DESCENT is out of range".into()));
+
+        let src = bdf("4 1 0 0", "4 1 0 0", "F0").replace("DWIDTH 4", "DWIDTH 4294967300");
+        assert_eq!(bdf_error(&src), (5, "DWIDTH is out of range".into()));
+    }
+
+    #[test]
+    fn huge_line_heights_saturate() {
+        let src = format!("FONT_ASCENT {}\nFONT_DESCENT 1\n", i32::MAX);
+        let src = src + &bdf("4 1 0 0", "4 1 0 0", "F0");
+        let font = Font::from_bdf(&src).unwrap();
+        assert_eq!(font.line_height(), i32::MAX as u32);
+        assert_eq!(font.measure("A\nA\nA"), (4, u32::MAX));
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_on_its_line() {
+        let bytes = b"STARTFONT 2.1\nFONT \xff\n";
+        match Font::from_bytes(bytes) {
+            Err(FontError::Bdf { line, .. }) => assert_eq!(line, 2),
+            other => panic!("expected a bdf error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn builtin_draws_text() {
+        let font = Font::builtin();
+        assert_eq!(font.line_height(), 10);
+        assert_eq!(font.measure("Hi"), (12, 10));
+
+        let mut buffer = PixelBuffer::new(12, 10);
+        let white = Pixel::new(255, 255, 255, 255);
+        buffer.draw_text(&font, 0, 0, "Hi", white, BlendMode::Replace);
+
+        let expected = [
+            "............",
+            "#...#...#...",
+            "#...#.......",
+            "#...#..##...",
+            "#####...#...",
+            "#...#...#...",
+            "#...#...#...",
+            "#...#..###..",
+            "............",
+            "............",
+        ];
+        assert_eq!(drawn(&buffer), expected);
+    }
+
+    #[test]
+    fn missing_chars_are_drawn_as_the_default_char() {
+        // The builtin font's DEFAULT_CHAR 0 is a dotted box
+        let font = Font::builtin();
+        assert_eq!(font.measure("\u{2603}"), (6, 10));
+        let white = Pixel::new(255, 255, 255, 255);
+        let mut buffer = PixelBuffer::new(6, 10);
+        buffer.draw_text(&font, 0, 0, "\u{2603}", white, BlendMode::Replace);
+        assert_eq!(drawn(&buffer)[1], "#.#.#.");
+    }
+
+    #[test]
+    fn missing_default_char_falls_back_to_a_question_mark() {
+        let glyph = |encoding| {
+            format!(
+                "STARTCHAR c\nENCODING {}\nDWIDTH 2 0\nBBX 1 1 0 0\nBITMAP\n80\nENDCHAR\n",
+                encoding
+            )
+        };
+        let header = "STARTFONT 2.1\nFONTBOUNDINGBOX 2 1 0 0\nDEFAULT_CHAR 0\n";
+
+        let font = Font::from_bdf(&format!("{}{}ENDFONT\n", header, glyph(63))).unwrap();
+        assert!(font.glyph('\u{2603}').is_some());
+        assert_eq!(font.measure("x?"), (4, 1));
+
+        // Nothing to fall back to
+        let font = Font::from_bdf(&format!("{}{}ENDFONT\n", header, glyph(65))).unwrap();
+        assert!(font.glyph('\u{2603}').is_none());
+        assert_eq!(font.measure("xA"), (2, 1));
+    }
+
+    #[test]
+    fn lines_and_tabs_are_measured() {
+        let font = Font::builtin();
+        assert_eq!(font.measure(""), (0, 0));
+        // The widest line decides the width
+        assert_eq!(font.measure("Hi\nHello\nA"), (30, 30));
+        assert_eq!(font.measure("Hello\r\nHi"), (30, 20));
+        assert_eq!(font.measure("Hi\n"), (12, 20));
+
+        // Tab stops are every 4 spaces, 24 pixels in this font
+        assert_eq!(font.measure("\t"), (24, 10));
+        assert_eq!(font.measure("\tA"), (30, 10));
+        assert_eq!(font.measure("Hello\tA"), (54, 10));
+        assert_eq!(font.measure("A\n\t\t"), (48, 20));
+    }
+
+    #[test]
+    fn tabs_move_the_pen() {
+        let font = Font::builtin();
+        let white = Pixel::new(255, 255, 255, 255);
+
+        let mut tabbed = PixelBuffer::new(36, 10);
+        tabbed.draw_text(&font, 0, 0, "i\tH", white, BlendMode::Replace);
+
+        let mut expected = PixelBuffer::new(36, 10);
+        expected.draw_text(&font, 0, 0, "i", white, BlendMode::Replace);
+        expected.draw_text(&font, 24, 0, "H", white, BlendMode::Replace);
+        assert_eq!(drawn(&tabbed), drawn(&expected));
+    }
+
+    #[test]
+    fn text_is_clipped() {
+        let font = Font::builtin();
+        let mut buffer = PixelBuffer::new(4, 4);
+        let white = Pixel::new(255, 255, 255, 255);
+
+        // The bottom of the right side of the first H, and the top of the
+        // second one
+        buffer.draw_text(&font, -3, -8, "Hi\nHi", white, BlendMode::Replace);
+        let expected = ["....", "....", "....", ".#.."];
+        assert_eq!(drawn(&buffer), expected);
+
+        // Right side of an H, the rest is outside
+        let mut buffer = PixelBuffer::new(4, 4);
+        buffer.draw_text(&font, -3, -5, "Hi", white, BlendMode::Replace);
+        let expected = [".#..", ".#..", ".#..", "...."];
+        assert_eq!(drawn(&buffer), expected);
+
+        // Entirely outside, nothing changes
+        buffer.draw_text(&font, i32::MAX, i32::MAX, "far", white, BlendMode::Replace);
+        buffer.draw_text(&font, i32::MIN, 0, "away", white, BlendMode::Replace);
+        buffer.draw_text(&font, 0, 4, "below", white, BlendMode::Replace);
+        assert_eq!(drawn(&buffer), expected);
+    }
+}